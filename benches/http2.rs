@@ -0,0 +1,59 @@
+//! Sequential request timing over http/1.1 only and h2-capable clients
+//!
+//! Run with `cargo bench --bench http2`, needs network access.
+//! Url and request count can be changed with BENCH_URL and BENCH_REQUESTS
+
+use hyper::{Body, Client, Request};
+use hyper_rustls::HttpsConnectorBuilder;
+use std::time::{Duration, Instant};
+
+const DEFAULT_URL: &str = "https://osu.ppy.sh/api/v2/rankings/osu/performance?cursor[page]=1";
+const DEFAULT_REQUESTS: u32 = 20;
+
+async fn run(http2: bool, url: &str, requests: u32) -> (hyper::Version, Duration) {
+    let builder = HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_only()
+        .enable_http1();
+
+    let https = match http2 {
+        true => builder.enable_http2().build(),
+        false => builder.build(),
+    };
+
+    let client = Client::builder().http2_only(false).build::<_, Body>(https);
+
+    let mut version = hyper::Version::HTTP_11;
+    let start = Instant::now();
+
+    for _ in 0..requests {
+        let req = Request::get(url)
+            .header("User-Agent", "vasteri-bebrik")
+            .body(Body::empty())
+            .unwrap();
+
+        let res = client.request(req).await.expect("request failed");
+        version = res.version();
+        hyper::body::to_bytes(res.into_body()).await.expect("body failed");
+    }
+
+    (version, start.elapsed())
+}
+
+#[tokio::main]
+async fn main() {
+    let url = std::env::var("BENCH_URL").unwrap_or_else(|_| DEFAULT_URL.to_string());
+    let requests = std::env::var("BENCH_REQUESTS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(DEFAULT_REQUESTS);
+
+    for http2 in [false, true] {
+        let (version, elapsed) = run(http2, &url, requests).await;
+
+        println!(
+            "h2 enabled: {http2:<5} negotiated: {version:?} {requests} requests in {elapsed:?} ({:?}/request)",
+            elapsed / requests
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use hyper::body::Bytes;
+use tracing::{debug, warn};
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::osu_api::ApiResult;
+
+/// Raw api responses stored as files under `dir`, entry
+/// is considered fresh for `ttl` after it was written
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    pub fn new(dir: impl Into<PathBuf>, ttl: Duration) -> Self {
+        Self {
+            dir: dir.into(),
+            ttl,
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.json"))
+    }
+
+    /// Fresh cached response, any io error is treated as a miss
+    pub fn get(&self, key: &str) -> Option<Bytes> {
+        let path = self.path(key);
+
+        let modified = path.metadata().and_then(|x| x.modified()).ok()?;
+        let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+
+        if age >= self.ttl {
+            return None;
+        }
+
+        std::fs::read(path).ok().map(Bytes::from)
+    }
+
+    pub fn put(&self, key: &str, bytes: &Bytes) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.path(key), bytes)
+    }
+
+    /// Cached response if it's fresh, otherwise the one from `fetch`
+    /// which is cached afterwards
+    pub async fn get_or_fetch<F, Fut>(&self, key: &str, fetch: F) -> ApiResult<Bytes>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = ApiResult<Bytes>>,
+    {
+        if let Some(bytes) = self.get(key) {
+            debug!("cache hit for {key}");
+            return Ok(bytes);
+        }
+
+        let bytes = fetch().await?;
+
+        if let Err(e) = self.put(key, &bytes) {
+            warn!("can't write {key} to cache: {e}");
+        }
+
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::ResponseCache;
+    use hyper::body::Bytes;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn cache_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("scrapper-cache-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_cache_hit_within_ttl() {
+        let dir = cache_dir("hit");
+        let cache = ResponseCache::new(&dir, Duration::from_secs(3600));
+        let fetches = AtomicUsize::new(0);
+
+        for _ in 0..2 {
+            let bytes = cache
+                .get_or_fetch("scores-1-osu", || async {
+                    fetches.fetch_add(1, Ordering::SeqCst);
+                    Ok(Bytes::from_static(b"[]"))
+                })
+                .await
+                .unwrap();
+
+            assert_eq!(bytes, Bytes::from_static(b"[]"));
+        }
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cache_expired() {
+        let dir = cache_dir("expired");
+        let cache = ResponseCache::new(&dir, Duration::ZERO);
+        let fetches = AtomicUsize::new(0);
+
+        for _ in 0..2 {
+            cache
+                .get_or_fetch("scores-1-osu", || async {
+                    fetches.fetch_add(1, Ordering::SeqCst);
+                    Ok(Bytes::from_static(b"[]"))
+                })
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
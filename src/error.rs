@@ -2,72 +2,185 @@ use hyper::body::Bytes;
 use hyper::http::Error;
 
 use serde::Deserialize;
+use thiserror::Error;
 
-use std::{error::Error as StdError, fmt};
+use std::fmt;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize)]
 pub struct ApiErrorResponse {
+    /// Often just a short code e.g. `invalid_token`
     pub error: String,
+    /// Human readable description
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Validation errors by field
+    #[serde(default)]
+    pub errors: Option<serde_json::Value>,
 }
 
-#[derive(Debug)]
+impl fmt::Display for ApiErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.error)?;
+
+        if let Some(message) = &self.message {
+            write!(f, " ({message})")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
 pub enum OsuApiError {
+    #[error("hyper error!")]
     HyperError {
+        #[from]
         inner: hyper::Error,
     },
+    #[error("hyper inner error!")]
     HyperHttpError {
+        #[from]
         inner: Error,
     },
+    #[error("got api error: {inner}!")]
     ApiError {
         inner: ApiErrorResponse,
     },
+    #[error("parsing error!")]
     ParsingError {
+        #[source]
         inner: serde_json::Error,
         body: Bytes,
     },
+    #[error("can't decompress response body!")]
+    DecodingError {
+        #[from]
+        inner: std::io::Error,
+    },
+    #[error("csv error!")]
+    CsvError {
+        #[from]
+        inner: csv::Error,
+    },
+    /// Anything reported through `eyre`, message is kept as is
+    #[error("{inner}")]
+    EyreError {
+        inner: eyre::Report,
+    },
+    #[error("bad request!")]
     BadRequest,
+    #[error("unauthorized, check CLIENT_ID and CLIENT_SECRET!")]
+    Unauthorized,
+    #[error("service is unavailable!")]
     ServiceUnavailable,
-    RateLimited,
+    #[error(
+        "got 429{}",
+        .retry_after.map(|x| format!(", retry after {}s", x.as_secs())).unwrap_or_default()
+    )]
+    RateLimited {
+        retry_after: Option<Duration>,
+        remaining: Option<u32>,
+    },
+    #[error("no token provided!")]
     NoToken,
+    #[error("client id and secret are required!")]
+    MissingCredentials,
+    #[error("request timed out!")]
+    Timeout,
+    #[error("unknown mod bits: {0}!")]
+    UnknownModBits(u32),
+    #[error("unknown mods: {}!", .0.join(", "))]
+    UnknownMods(Vec<String>),
+    #[error("ranking has no users!")]
+    EmptyRanking,
+    #[error("request budget is exhausted!")]
+    BudgetExhausted,
 }
 
-impl From<Error> for OsuApiError {
-    fn from(value: Error) -> Self {
-        Self::HyperHttpError { inner: value }
+impl OsuApiError {
+    /// Whether request that failed with this error is worth retrying
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            OsuApiError::HyperError { .. }
+                | OsuApiError::ServiceUnavailable
+                | OsuApiError::RateLimited { .. }
+                | OsuApiError::Timeout
+        )
     }
 }
 
-impl From<hyper::Error> for OsuApiError {
-    fn from(value: hyper::Error) -> Self {
-        Self::HyperError { inner: value }
+// `eyre::Report` isn't `std::error::Error` so it can't be `#[from]`
+impl From<eyre::Report> for OsuApiError {
+    fn from(value: eyre::Report) -> Self {
+        Self::EyreError { inner: value }
     }
 }
-impl StdError for OsuApiError {
-    fn source(&self) -> Option<&(dyn StdError + 'static)> {
-        match self {
-            OsuApiError::HyperError { inner } => Some(inner),
-            OsuApiError::HyperHttpError { inner } => Some(inner),
-            OsuApiError::ApiError { .. } => None,
-            OsuApiError::ParsingError { inner, .. } => Some(inner),
-            OsuApiError::BadRequest => None,
-            OsuApiError::ServiceUnavailable => None,
-            OsuApiError::RateLimited => None,
-            OsuApiError::NoToken => None,
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{ApiErrorResponse, OsuApiError};
+    use std::error::Error;
+    use std::time::Duration;
+
+    fn read_csv() -> Result<(), OsuApiError> {
+        let mut reader = csv::Reader::from_reader("a,b\n1\n".as_bytes());
+        for record in reader.records() {
+            record?;
         }
+
+        Ok(())
     }
-}
 
-impl fmt::Display for OsuApiError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            OsuApiError::HyperError { .. } => f.write_str("hyper error!"),
-            OsuApiError::HyperHttpError { .. } => f.write_str("hyper inner error!"),
-            OsuApiError::ApiError { .. } => f.write_str("got api error!"),
-            OsuApiError::ParsingError { .. } => f.write_str("parsing error!"),
-            OsuApiError::BadRequest => f.write_str("bad request!"),
-            OsuApiError::ServiceUnavailable => f.write_str("service is unavailable!"),
-            OsuApiError::RateLimited => f.write_str("got 429"),
-            OsuApiError::NoToken => f.write_str("no token provided!"),
-        }
+    fn check_positive(x: i32) -> Result<i32, OsuApiError> {
+        let check = || -> eyre::Result<i32> {
+            if x <= 0 {
+                eyre::bail!("{x} is not positive");
+            }
+            Ok(x)
+        };
+
+        Ok(check()?)
+    }
+
+    #[test]
+    fn test_error_conversions() {
+        let err = read_csv().unwrap_err();
+        assert!(matches!(err, OsuApiError::CsvError { .. }));
+        assert!(err.source().is_some());
+
+        assert_eq!(check_positive(1).unwrap(), 1);
+        assert_eq!(check_positive(-1).unwrap_err().to_string(), "-1 is not positive");
+
+        let rate_limited = OsuApiError::RateLimited {
+            retry_after: Some(Duration::from_secs(3)),
+            remaining: None,
+        };
+        assert_eq!(rate_limited.to_string(), "got 429, retry after 3s");
+        assert_eq!(
+            OsuApiError::RateLimited { retry_after: None, remaining: None }.to_string(),
+            "got 429"
+        );
+    }
+
+    #[test]
+    fn test_api_error_message() {
+        let parse = |body: &str| OsuApiError::ApiError {
+            inner: serde_json::from_str::<ApiErrorResponse>(body).unwrap(),
+        };
+
+        let err = parse(r#"{"error":"invalid_token","message":"The access token provided is expired."}"#);
+        assert_eq!(err.to_string(), "got api error: invalid_token (The access token provided is expired.)!");
+        assert!(err.source().is_none());
+
+        let err = parse(r#"{"error":"invalid_request"}"#);
+        assert_eq!(err.to_string(), "got api error: invalid_request!");
+
+        let OsuApiError::ApiError { inner } = parse(r#"{"error":"","errors":{"mode":["is invalid"]}}"#) else {
+            unreachable!()
+        };
+        assert_eq!(inner.errors.unwrap()["mode"][0], "is invalid");
+        assert_eq!(inner.message, None);
     }
 }
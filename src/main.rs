@@ -1,205 +1,2010 @@
+mod cache;
+mod countries;
 mod error;
+mod metrics;
 mod osu_api;
+mod output;
+mod util;
 
-use crate::osu_api::{ OsuApi, RankingType };
+use crate::cache::ResponseCache;
+use crate::countries::is_country_code;
+use crate::error::OsuApiError;
+use crate::osu_api::{
+    GameMode, OsuApi, OsuMods, RankingMetric, RankingType, Score, ScoreType, UserCompact, UserLookup,
+    WHOLE_RANKING,
+};
+use crate::output::{
+    Output, OutputFormat, Writer, create_writer, open_output, parse_column, read_rows, unique_beatmaps,
+    with_precision,
+};
+use crate::util::{confirm, parse_date, setting_or_env};
 use clap::Parser;
 
-use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use osu_api::UserStatistics;
-use serde::Serialize;
-use std::{fs::File, sync::Arc};
+use std::collections::HashMap;
+use std::fmt;
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::{path::{Path, PathBuf}, num::{NonZeroU32, NonZeroUsize}, sync::Arc, time::Duration};
 
-use tokio::sync::mpsc::{Sender, channel};
+use tokio::sync::mpsc::{Receiver, Sender, channel};
+use tokio::time::Instant;
 
 use dotenv::dotenv;
+use futures::future::try_join_all;
 use std::env;
 
 use eyre::Result;
+use tracing::{debug, warn};
+use tracing_subscriber::EnvFilter;
 
-macro_rules! str_to_datetime {
-    ($s:expr) => {{
-        let naivedate = NaiveDate::parse_from_str($s, "%d-%m-%Y")?;
+#[derive(Debug, Clone)]
+pub struct Period {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>
+}
+
+impl Period {
+    fn new(from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Self> {
+        if from >= to {
+            eyre::bail!("period start ({from}) should be before its end ({to})");
+        }
+
+        Ok(Self { from, to })
+    }
+
+    fn contains(&self, date: &DateTime<Utc>) -> bool {
+        *date > self.from && *date < self.to
+    }
+}
+
+/// Parses `from:to` pair of dates e.g. 01-05-2023:31-05-2023
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((from, to)) = s.split_once(':') else {
+            return Err(format!("{s} should be in from:to form e.g. 01-05-2023:31-05-2023"));
+        };
+
+        let from = parse_date(from).map_err(|e| format!("invalid period start: {e}"))?;
+        let to = parse_date(to).map_err(|e| format!("invalid period end: {e}"))?;
+
+        Period::new(from, to).map_err(|e| e.to_string())
+    }
+}
+
+/// Score filters applied on top of the period
+#[derive(Debug, Clone, Default)]
+pub struct Filters {
+    min_score: i64,
+    min_pp: f32,
+    min_accuracy: f32,
+    mods: Option<OsuMods>,
+    exclude_mods: Option<OsuMods>,
+    beatmap: Option<i64>,
+    beatmapset: Option<i64>,
+    max_length: Option<u32>,
+    min_bpm: Option<f32>,
+    classic_only: bool,
+}
+
+impl Filters {
+    /// Score should contain every mod of `mods` and none of `exclude_mods`
+    fn matches_mods(&self, mods: OsuMods) -> bool {
+        self.mods.is_none_or(|include| mods.contains(include))
+            && self.exclude_mods.is_none_or(|exclude| !mods.intersects(exclude))
+    }
+
+    /// Score should reach `min_score`, `min_pp` (missing pp counts as 0) and
+    /// `min_accuracy` (in percent), only classic scores pass with `classic_only`
+    fn matches_stats(&self, score: i64, pp: Option<f32>, accuracy: f32, classic: bool) -> bool {
+        score >= self.min_score
+            && pp.unwrap_or(0.0) >= self.min_pp
+            && accuracy >= self.min_accuracy
+            && (!self.classic_only || classic)
+    }
+
+    fn matches_map(&self, score: &Score) -> bool {
+        self.matches_beatmap(
+            score.beatmap.id,
+            score.beatmapset.id,
+            score.beatmap.total_length,
+            score.beatmap.bpm,
+        )
+    }
+
+    /// Score should be set on `beatmap` and within `beatmapset`, map should be
+    /// at most `max_length` long and at least `min_bpm` fast (if they're given)
+    fn matches_beatmap(&self, beatmap: i64, beatmapset: i64, length: u32, bpm: f32) -> bool {
+        self.beatmap.is_none_or(|id| beatmap == id)
+            && self.beatmapset.is_none_or(|id| beatmapset == id)
+            && self.max_length.is_none_or(|max| length <= max)
+            && self.min_bpm.is_none_or(|min| bpm >= min)
+    }
+
+    /// Same checks as for api scores but against previously written row
+    fn matches_row(&self, row: &Output) -> bool {
+        let accuracy = row.accuracy.trim_end_matches('%').parse().unwrap_or(0.0);
+        let mods = row.mods.parse().unwrap_or_default();
+        let (beatmapset, beatmap) = parse_beatmap_link(&row.beatmap_link).unwrap_or_default();
+
+        self.matches_stats(row.total_score, row.pp, accuracy, !row.scorev2)
+            && self.matches_mods(mods)
+            && self.matches_beatmap(beatmap, beatmapset, row.length_seconds, row.bpm)
+    }
+}
+
+/// Beatmapset and beatmap ids out of e.g. https://osu.ppy.sh/beatmapsets/1#osu/2
+fn parse_beatmap_link(link: &str) -> Option<(i64, i64)> {
+    let (_, rest) = link.split_once("/beatmapsets/")?;
+    let (beatmapset, rest) = rest.split_once('#')?;
+    let (_, beatmap) = rest.rsplit_once('/')?;
+
+    Some((beatmapset.parse().ok()?, beatmap.parse().ok()?))
+}
+
+/// How `date` column is rendered
+#[derive(Debug, Clone)]
+pub struct DateFormat {
+    format: String,
+    offset: FixedOffset,
+}
+
+impl DateFormat {
+    fn new(format: &str, utc_offset: i32) -> Result<Self> {
+        if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+            eyre::bail!("invalid --date-format: {format}");
+        }
+
+        let offset = utc_offset
+            .checked_mul(3600)
+            .and_then(FixedOffset::east_opt)
+            .ok_or_else(|| eyre::eyre!("invalid --utc-offset: {utc_offset}"))?;
+
+        Ok(Self {
+            format: format.to_owned(),
+            offset,
+        })
+    }
 
-        // Should never fails so using unwrap
-        let naivetime = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+    fn format(&self, date: &DateTime<Utc>) -> String {
+        date.with_timezone(&self.offset)
+            .format(&self.format)
+            .to_string()
+    }
 
-        let ndt = NaiveDateTime::new(naivedate, naivetime);
-        let r: DateTime<Utc> = DateTime::from_naive_utc_and_offset(ndt, Utc);
+    /// Reverse of `format`, dates without time are taken as midnight.
+    /// `None` if format loses too much to restore the date
+    fn parse(&self, s: &str) -> Option<DateTime<Utc>> {
+        let naive = NaiveDateTime::parse_from_str(s, &self.format)
+            .or_else(|_| NaiveDate::parse_from_str(s, &self.format).map(|x| x.and_time(NaiveTime::MIN)))
+            .ok()?;
 
-        r
-    }};
+        Some(naive.and_local_timezone(self.offset).single()?.with_timezone(&Utc))
+    }
 }
 
+/// Everything (besides the user) that's needed to process user's scores
 #[derive(Debug, Clone)]
-pub struct Period {
-    from: DateTime<Utc>,
-    to: DateTime<Utc>
+pub struct ScrapeOptions {
+    period: Period,
+    filters: Filters,
+    limit: u32,
+    date_format: DateFormat,
+    precision: usize,
+    unique_beatmaps: bool,
+    check_replays: bool,
+    require_replay: bool,
+    mode: GameMode,
+    score_type: ScoreType,
+    max_pages: Option<u32>,
+}
+
+impl ScrapeOptions {
+    /// Whether score passes period and every filter
+    fn accepts(&self, score: &Score) -> bool {
+        let filters = &self.filters;
+
+        self.period.contains(&score.created_at)
+            && filters.matches_stats(score.score, score.pp, score.accuracy * 100.0, score.is_classic_scoring())
+            && filters.matches_mods(score.mods)
+            && filters.matches_map(score)
+            && (score.passed || self.score_type == ScoreType::Recent { include_fails: true })
+    }
+}
+
+/// Slice of the leaderboard (by rank) that should be processed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RankWindow {
+    offset: usize,
+    count: usize,
+}
+
+impl RankWindow {
+    /// Builds window from optional 1-based inclusive ranks, falling
+    /// back to `amount` users starting from `from_rank`. Zero `amount`
+    /// means rest of the leaderboard
+    fn new(from_rank: Option<usize>, to_rank: Option<usize>, amount: usize) -> Result<Self> {
+        let from = from_rank.unwrap_or(1);
+
+        if from == 0 {
+            eyre::bail!("--from-rank starts from 1");
+        }
+
+        let to = match (to_rank, amount) {
+            (Some(to), _) => to,
+            (None, 0) => WHOLE_RANKING,
+            (None, amount) => from.checked_add(amount - 1).ok_or_else(|| {
+                eyre::eyre!("--from-rank ({from}) and --amount ({amount}) go past the last possible rank")
+            })?,
+        };
+
+        if to < from {
+            eyre::bail!("--to-rank ({to}) should be greater or equal to --from-rank ({from})");
+        }
+
+        Ok(Self {
+            offset: from - 1,
+            count: to - from + 1,
+        })
+    }
+
+    /// Amount of top leaderboard users required to cover the window
+    fn end(&self) -> usize {
+        self.offset + self.count
+    }
+
+    /// Shrinks window to fit into leaderboard with `total` players
+    fn cap(&mut self, total: usize) {
+        self.count = self.count.min(total.saturating_sub(self.offset));
+    }
+}
+
+/// Validates country code against ISO 3166-1 list and normalizes it to uppercase
+fn parse_country_code(s: &str) -> Result<String, String> {
+    if s.len() != 2 || !s.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(format!("{s} is not a two-letter country code"));
+    }
+
+    if !is_country_code(s) {
+        return Err(format!("{} is not a recognized country code", s.to_uppercase()));
+    }
+
+    Ok(s.to_uppercase())
+}
+
+/// Parses percentage in [0.0, 100.0] range
+fn parse_percent(s: &str) -> Result<f32, String> {
+    let value: f32 = s.parse().map_err(|_| format!("{s} is not a number"))?;
+
+    if !(0.0..=100.0).contains(&value) {
+        return Err(format!("{value} is not in 0.0..=100.0 range"));
+    }
+
+    Ok(value)
+}
+
+/// Picks leaderboard to fetch, returns a warning along with it
+/// when `--country` is ignored because of `--global`
+fn ranking_type(
+    global: bool,
+    mut countries: Vec<String>,
+    spotlight: Option<u32>,
+) -> Result<(RankingType, Option<String>)> {
+    if let Some(id) = spotlight {
+        return Ok((RankingType::Spotlight { id }, None));
+    }
+
+    countries.sort();
+    countries.dedup();
+
+    match (global, countries.len()) {
+        (true, 0) => Ok((RankingType::Global, None)),
+        (true, _) => Ok((
+            RankingType::Global,
+            Some(format!(
+                "--global overrides --country {}, fetching global leaderboard",
+                countries.join(",")
+            )),
+        )),
+        (false, 0) => eyre::bail!("either --global, --country or --spotlight should be provided"),
+        (false, 1) => Ok((RankingType::Country { code: countries.remove(0) }, None)),
+        (false, _) => Ok((RankingType::Countries { codes: countries }, None)),
+    }
+}
+
+/// Parses users file contents, blank lines are skipped and
+/// every invalid line is reported with its line number
+fn parse_users_file(content: &str) -> Result<Vec<UserLookup>> {
+    let mut users = Vec::new();
+    let mut bad_lines = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        match line.parse::<UserLookup>() {
+            Ok(user) => users.push(user),
+            Err(_) => bad_lines.push(format!("line {}: {line:?}", i + 1)),
+        }
+    }
+
+    if !bad_lines.is_empty() {
+        eyre::bail!("invalid users in users file:\n{}", bad_lines.join("\n"));
+    }
+
+    if users.is_empty() {
+        eyre::bail!("users file is empty");
+    }
+
+    Ok(users)
 }
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 struct Args {
-    /// Start date (%d-%m-%Y) e.g. 01-05-2023
-    #[arg(short, long)]
-    pub from: String,
+    /// Period as start:end dates (%d-%m-%Y, %Y-%m-%d or %d/%m/%Y) e.g. 01-05-2023:31-05-2023
+    #[arg(short, long, conflicts_with_all(["from", "to"]), value_parser = Period::from_str)]
+    pub period: Option<Period>,
 
-    /// End date (%d-%m-%Y) e.g. 01-05-2023
-    #[arg(short, long)]
-    pub to: String,
+    /// Start date, deprecated in favor of --period
+    #[arg(short, long, required_unless_present_any(["score_id", "period", "input", "since_last_run", "check"]))]
+    pub from: Option<String>,
+
+    /// End date, deprecated in favor of --period
+    #[arg(short, long, required_unless_present_any(["score_id", "period", "input", "since_last_run", "check"]))]
+    pub to: Option<String>,
+
+    /// Period from the end of the last successful run (kept in --state-file) up to now.
+    /// Before the first run it's --period or, with --append, the latest score date
+    /// in existing --output (csv, json or jsonl)
+    #[arg(long, conflicts_with_all(["from", "to"]))]
+    pub since_last_run: bool,
+
+    /// File the end of the last successful --since-last-run period is kept in
+    #[arg(long, default_value = "last_run.txt")]
+    pub state_file: PathBuf,
 
     /// Fetch global leaderboard? If set to true overrides --country flag
     #[arg(short, long)]
     pub global: bool,
 
-    /// Country code e.g. BY, US, UK, BE, JP
-    #[arg(short, long, required_unless_present("global"))]
-    pub country: Option<String>,
+    /// Country code e.g. BY, US, GB, BE, JP. Several comma separated codes
+    /// are fetched in parallel and merged into one leaderboard
+    #[arg(short, long, required_unless_present_any(["global", "spotlight", "users_file", "score_id", "input", "check"]), value_delimiter = ',', value_parser = parse_country_code)]
+    pub country: Vec<String>,
+
+    /// Spotlight id, fetches spotlight (charts) leaderboard instead
+    #[arg(long, conflicts_with_all(["global", "country"]))]
+    pub spotlight: Option<u32>,
+
+    /// File with newline-separated user ids or usernames to process instead of a leaderboard
+    #[arg(long, conflicts_with_all(["global", "country", "spotlight"]))]
+    pub users_file: Option<String>,
+
+    /// Previously written csv, json or jsonl output to filter again instead
+    /// of fetching anything, --period is optional here
+    #[arg(long, conflicts_with_all(["global", "country", "spotlight", "users_file", "score_id"]))]
+    pub input: Option<PathBuf>,
 
+    /// Only check that credentials are valid and exit
+    #[arg(long, conflicts_with_all(["global", "country", "spotlight", "users_file", "score_id", "input"]))]
+    pub check: bool,
 
-    /// Amount of users to process
+    /// Fetch and print a single score instead of scraping a leaderboard
+    #[arg(long, conflicts_with_all(["global", "country", "spotlight", "users_file"]))]
+    pub score_id: Option<i64>,
+
+
+    /// Amount of users to process, 0 for the whole leaderboard
     #[arg(short, long, default_value_t = 200)]
-    pub amount: i32,
+    pub amount: usize,
+
+    /// Don't ask for confirmation before fetching the whole leaderboard
+    #[arg(short, long)]
+    pub yes: bool,
+
+    /// First leaderboard rank to process (inclusive)
+    #[arg(long)]
+    pub from_rank: Option<usize>,
+
+    /// Last leaderboard rank to process (inclusive), overrides --amount
+    #[arg(long)]
+    pub to_rank: Option<usize>,
+
+    /// Amount of best or recent scores to fetch per user (max 100)
+    #[arg(long, default_value_t = 100)]
+    pub per_user_limit: u32,
+
+    /// Leaderboard to fetch: pp or score (ranked score, global only)
+    #[arg(long, default_value = "pp", value_parser = RankingMetric::from_str)]
+    pub metric: RankingMetric,
+
+    /// Game mode: osu, taiko, fruits or mania
+    #[arg(long, default_value = "osu", value_parser = GameMode::from_str)]
+    pub mode: GameMode,
+
+    /// Which scores of every user to fetch: best, recent or firsts
+    #[arg(long = "type", default_value = "best", value_parser = ScoreType::from_str)]
+    pub score_type: ScoreType,
+
+    /// Maximum pages (100 scores each) of firsts to fetch per user, unlimited by default
+    #[arg(long)]
+    pub max_pages: Option<u32>,
+
+    /// Include failed scores when fetching recent scores
+    #[arg(long)]
+    pub include_fails: bool,
+
+    /// Minimum raw score (not pp) for score to be included
+    #[arg(long, default_value_t = 0)]
+    pub min_score: i64,
+
+    /// Minimum pp for score to be included, scores without pp count as 0
+    #[arg(long, default_value_t = 0.0)]
+    pub min_pp: f32,
+
+    /// Minimum accuracy in percent e.g. 95.0
+    #[arg(long, default_value_t = 0.0, value_parser = parse_percent)]
+    pub min_accuracy: f32,
+
+    /// Only include scores with all of these mods e.g. HDDT
+    #[arg(long, value_parser = OsuMods::from_str_strict)]
+    pub mods: Option<OsuMods>,
+
+    /// Skip scores with any of these mods e.g. EZHTNF
+    #[arg(long, value_parser = OsuMods::from_str_strict)]
+    pub exclude_mods: Option<OsuMods>,
+
+    /// Skip ScoreV2 scores
+    #[arg(long)]
+    pub classic_only: bool,
+
+    /// Only include scores set on this beatmap (difficulty) id
+    #[arg(long)]
+    pub beatmap: Option<i64>,
+
+    /// Only include scores set on this beatmapset id
+    #[arg(long)]
+    pub beatmapset: Option<i64>,
+
+    /// Skip maps longer than this many seconds (total length, breaks included)
+    #[arg(long)]
+    pub max_length: Option<u32>,
+
+    /// Skip maps slower than this bpm
+    #[arg(long)]
+    pub min_bpm: Option<f32>,
+
+    /// Timeout for a single api request in seconds [env: OSU_TIMEOUT] [default: 30]
+    #[arg(long)]
+    pub timeout: Option<u64>,
+
+    /// How many times failed api request is retried [env: OSU_MAX_RETRIES] [default: 3]
+    #[arg(long)]
+    pub max_retries: Option<u32>,
+
+    /// Maximum amount of api requests in flight at the same time [env: OSU_CONCURRENCY]
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+
+    /// Maximum amount of api requests per minute [env: OSU_RPM]
+    #[arg(long)]
+    pub rpm: Option<NonZeroU32>,
+
+    /// Directory to cache users scores and leaderboard pages in, disabled if not set
+    #[arg(long)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// How long cached responses stay fresh in seconds
+    #[arg(long, default_value_t = 3600)]
+    pub cache_ttl: u64,
+
+    /// Capacity of the channel between fetching tasks and output writer
+    #[arg(long, default_value = "1024")]
+    pub channel_buffer: NonZeroUsize,
+
+    /// Format of the date column (chrono specifiers)
+    #[arg(long, default_value = "%Y-%m-%d %H:%M:%S")]
+    pub date_format: String,
+
+    /// Shift displayed dates by this amount of hours
+    #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+    pub utc_offset: i32,
+
+    /// Decimal places kept in pp and accuracy columns
+    #[arg(long, default_value_t = 2)]
+    pub precision: usize,
+
+    /// Keep only the highest pp score of every beatmap within each user's scores
+    #[arg(long)]
+    pub unique_beatmaps: bool,
+
+    /// Keep only the highest pp row of every beatmap across all users
+    #[arg(long)]
+    pub unique_beatmaps_global: bool,
+
+    /// Skip scores without replay
+    #[arg(long)]
+    pub require_replay: bool,
+
+    /// Verify that replays can be downloaded (one extra request per score with replay)
+    #[arg(long)]
+    pub check_replays: bool,
+
+    /// Stop fetching after this many seconds and write what's collected so far
+    #[arg(long)]
+    pub max_duration: Option<u64>,
+
+    /// Stop fetching after this many api requests and write what's collected so far
+    #[arg(long)]
+    pub max_requests: Option<usize>,
+
+    /// Print request count and latency summary at the end of the run
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Only fetch the leaderboard and print users that would be processed
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    pub format: OutputFormat,
+
+    /// Comma-separated list of columns to write e.g. username,pp,mods (csv, json and jsonl)
+    #[arg(long, alias = "output-columns", value_delimiter = ',', value_parser = parse_column)]
+    pub columns: Option<Vec<String>>,
+
+    /// Output file path, defaults to output.csv or output.md depending on --format
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// Append to existing csv output instead of overwriting it, scores
+    /// already present in it are skipped
+    #[arg(long)]
+    pub append: bool,
 }
 
-#[derive(Debug, Serialize)]
-struct Output {
+/// Per-user aggregates reported after the run
+#[derive(Debug)]
+pub struct UserSummary {
     username: String,
-    pp: f32,
-    date: String,
-    replay: bool,
-    score_link: String,
-    map: String,
-    diff: String,
-    mods: String,
-    country_rank: i32,
-    global_rank: i32,
-    total_pp: f32,
-}
-
-async fn fetch_thread(
+    period_weighted_pp: f32,
+}
+
+/// User whose scores couldn't be processed
+#[derive(Debug)]
+pub struct UserFailure {
+    username: String,
+    user_id: i64,
+    error: String,
+    /// Failed on a transient error that didn't go away after every retry
+    retries_exhausted: bool,
+}
+
+impl UserFailure {
+    fn new(user: &UserCompact, err: &eyre::Report) -> Self {
+        Self {
+            username: user.username.clone(),
+            user_id: user.id,
+            error: err.to_string(),
+            retries_exhausted: err
+                .downcast_ref::<OsuApiError>()
+                .is_some_and(OsuApiError::is_retryable),
+        }
+    }
+}
+
+/// Outcome of processing every user
+#[derive(Debug, Default)]
+pub struct ScrapeReport {
+    /// Only filled for best scores
+    summaries: Vec<UserSummary>,
+    failures: Vec<UserFailure>,
+}
+
+/// Pp that score contributes to user's total when it's `index`-th (from 0) best score
+fn weighted_pp(score: &Score, index: usize) -> f32 {
+    score.pp.unwrap_or(0.0) * 0.95f32.powi(index as i32)
+}
+
+/// Weighted pp contribution of in-period scores, `scores` are expected
+/// to be ordered the same way as user's best scores (by pp descending)
+fn period_weighted_pp(scores: &[Score], period: &Period) -> f32 {
+    scores
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| period.contains(&x.created_at))
+        .map(|(i, x)| weighted_pp(x, i))
+        .sum()
+}
+
+/// Fetches users' scores and turns the ones passing filters into output rows
+#[derive(Clone)]
+pub struct Scraper {
     api: Arc<OsuApi>,
-    tx: Sender<Output>,
-    users: Vec<UserStatistics>,
-    amount: usize,
-    period: Period
-) {
-    for (index, user_stats) in users
+    options: ScrapeOptions,
+}
+
+impl Scraper {
+    pub fn new(api: Arc<OsuApi>, options: ScrapeOptions) -> Self {
+        Self { api, options }
+    }
+
+    /// Processes every user concurrently, rows are sent to `tx` as soon
+    /// as they're ready. Users cut off by the run budget aren't reported as failed
+    ///
+    /// Failures are collected from each user's task handle rather than a second
+    /// channel, so every spawned user ends up either in the report or cut off
+    /// by the budget, panicked tasks included
+    pub async fn run(&self, users: Vec<UserStatistics>, tx: Sender<Output>) -> ScrapeReport {
+        let mut handles = Vec::with_capacity(users.len());
+
+        for user_stats in users {
+            let scraper = self.clone();
+            let tx = tx.clone();
+            let user = user_stats.user.clone();
+
+            let handle = tokio::spawn(async move {
+                scraper.process_user(tx, user_stats).await
+            });
+
+            handles.push((user, handle));
+        }
+
+        let mut report = ScrapeReport::default();
+
+        for (user, handle) in handles {
+            let err = match handle.await {
+                Ok(Ok(summary)) => {
+                    report.summaries.extend(summary);
+                    continue;
+                }
+                Ok(Err(e)) => e,
+                Err(e) => e.into(),
+            };
+
+            if matches!(err.downcast_ref(), Some(OsuApiError::BudgetExhausted)) {
+                continue;
+            }
+
+            report.failures.push(UserFailure::new(&user, &err));
+        }
+
+        report
+    }
+
+    /// Summary is `None` unless best scores are fetched, weighting
+    /// by position means nothing for other lists
+    async fn process_user(
+        &self,
+        tx: Sender<Output>,
+        user_stats: UserStatistics,
+    ) -> Result<Option<UserSummary>> {
+        let api = &self.api;
+        let user = &user_stats.user;
+        let ScrapeOptions {
+            period,
+            limit,
+            date_format,
+            precision,
+            unique_beatmaps,
+            check_replays,
+            require_replay,
+            mode,
+            score_type,
+            max_pages,
+            ..
+        } = &self.options;
+
+        println!("Processing user {}", user.username);
+
+        // Getting scores
+        // Firsts can go way beyond a single page
+        let scores = match score_type {
+            ScoreType::Firsts => {
+                api.get_user_scores_all(user.id, *mode, *score_type, *max_pages).await?
+            }
+            _ => api.get_user_scores(user.id, *mode, *score_type, *limit).await?,
+        };
+
+        let candidates = match unique_beatmaps {
+            true => unique_beatmap_scores(&scores),
+            false => scores.iter().enumerate().collect(),
+        };
+
+        for (index, score) in candidates
+            .into_iter()
+            .filter(|(_, x)| self.options.accepts(x))
+        {
+            if *require_replay && !score.replay {
+                debug!(
+                    "Skipping score {} for user {}: no replay available",
+                    score.id, user.username
+                );
+                continue;
+            }
+
+            let mut output = score_output(score, &user_stats, date_format, *precision);
+
+            // Position means something only in the best scores list
+            if *score_type == ScoreType::Best {
+                output.weighted_pp = Some(weighted_pp(score, index));
+            }
+
+            if *check_replays {
+                // Failed check leaves availability unknown rather than dropping the rest of rows
+                output.replay_available = match score.replay {
+                    true => match api.is_replay_available(*mode, score.id).await {
+                        Ok(available) => Some(available),
+                        Err(e) => {
+                            warn!("can't check replay of score {}: {e}", score.id);
+                            None
+                        }
+                    },
+                    false => Some(false),
+                };
+            }
+
+            // Receiver is gone only when writing failed, nothing to send rows to
+            if tx.send(output).await.is_err() {
+                eyre::bail!("output writer stopped before user {} was processed", user.username);
+            }
+        }
+
+        if *score_type != ScoreType::Best {
+            return Ok(None);
+        }
+
+        Ok(Some(UserSummary {
+            username: user.username.clone(),
+            period_weighted_pp: period_weighted_pp(&scores, period),
+        }))
+    }
+}
+
+/// Highest pp score of every beatmap along with its position in `scores`,
+/// order is kept
+fn unique_beatmap_scores(scores: &[Score]) -> Vec<(usize, &Score)> {
+    let mut best: HashMap<i64, usize> = HashMap::new();
+
+    for (index, score) in scores.iter().enumerate() {
+        let pp = score.pp.unwrap_or(0.0);
+
+        best.entry(score.beatmap.id)
+            .and_modify(|x| {
+                if pp > scores[*x].pp.unwrap_or(0.0) {
+                    *x = index;
+                }
+            })
+            .or_insert(index);
+    }
+
+    scores
         .iter()
         .enumerate()
-        .take(amount) 
-    {
-        let stats = user_stats.clone();
-        let tx = tx.clone();
-        let api = Arc::clone(&api);
-        let period = period.clone();
+        .filter(|(index, score)| best[&score.beatmap.id] == *index)
+        .collect()
+}
 
-        tokio::spawn(async move {
-            let _ = process_score(
-                Arc::clone(&api),
-                tx,
-                stats,
-                index,
-                period
-            ).await;
-        });
+fn score_output(
+    score: &Score,
+    user_stats: &UserStatistics,
+    date_format: &DateFormat,
+    precision: usize,
+) -> Output {
+    let user = &user_stats.user;
+
+    Output {
+        username: user.username.clone(),
+        user_id: score.user_id,
+        profile_link: score.profile_link(),
+        country: user.country_code.clone(),
+        pp: score.pp,
+        weighted_pp: None,
+        accuracy: format!("{:.precision$}%", score.accuracy * 100.0),
+        total_score: score.score,
+        scorev2: !score.is_classic_scoring(),
+        date: date_format.format(&score.created_at),
+        replay: score.replay,
+        replay_available: None,
+        map: format!("{} - {}", score.beatmapset.artist, score.beatmapset.title),
+        diff: score.beatmap.version.clone(),
+        length_seconds: score.beatmap.total_length,
+        bpm: score.beatmap.bpm,
+        pp_per_minute: score.pp_per_minute(),
+        score_link: score.score_link(),
+        beatmap_link: score.beatmap_link(),
+        mods: score.mods.to_string(),
+        max_combo: score.max_combo,
+        count_geki: (score.mode == GameMode::Mania).then_some(score.statistics.count_geki),
+        count_katu: (score.mode == GameMode::Mania).then_some(score.statistics.count_katu),
+        is_fc: score.is_fc(),
+        passed: score.passed,
+        perfect: score.perfect,
+        country_rank: user_stats.country_rank,
+        global_rank: user_stats.global_rank,
+        total_pp: user_stats.pp,
     }
 }
 
-async fn process_score(
-    api: Arc<OsuApi>, 
-    tx: Sender<Output>,
-    user_stats: UserStatistics,
-    index: usize,
-    period: Period
+/// Prints single score (with its owner's stats) to stdout
+async fn print_score(
+    api: &OsuApi,
+    mode: GameMode,
+    score_id: i64,
+    format: OutputFormat,
+    columns: Option<Vec<String>>,
+    date_format: &DateFormat,
+    precision: usize,
 ) -> Result<()> {
-    let user = &user_stats.user;
+    let score = api.get_score_by_id(mode, score_id).await?;
+    let user = api.get_user(mode, &UserLookup::Id(score.user_id)).await?;
 
-    println!("Processing user {}", user.username);
+    let wtr = create_writer(format, Box::new(std::io::stdout()), columns)?;
+    let mut wtr = with_precision(wtr, format, precision);
+    wtr.write_row(&score_output(&score, &user.into(), date_format, precision))?;
+    wtr.finish()?;
 
-    // Getting scores
-    let scores = api.get_user_best_scores(user.id).await?;
+    Ok(())
+}
 
-    for score in scores
-        .iter()
-        .filter(|&x| x.created_at > period.from && x.created_at < period.to)
-        {
-            let _ = tx.send(Output {
-                username: user.username.clone(),
-                pp: score.pp.unwrap_or(0.0),
-                date: score.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
-                replay: score.replay,
-                map: format!("{} - {}", score.beatmapset.artist, score.beatmapset.title),
-                diff: score.beatmap.version.clone(),
-                score_link: format!("https://osu.ppy.sh/scores/{}", score.id),
-                mods: score.mods.to_string(),
-                country_rank: index as i32 + 1,
-                global_rank: user_stats.global_rank,
-                total_pp: user_stats.pp,
-            }).await;
+/// Tunables can also come from environment, command line takes precedence
+async fn build_api(args: &Args) -> Result<OsuApi> {
+    let mut builder = OsuApi::builder()
+        .client_id(env::var("CLIENT_ID")?.parse()?)
+        .client_secret(env::var("CLIENT_SECRET")?.as_str());
+
+    if let Some(timeout) = setting_or_env(args.timeout, "OSU_TIMEOUT")? {
+        builder = builder.timeout(Duration::from_secs(timeout));
+    }
+
+    if let Some(max_retries) = setting_or_env(args.max_retries, "OSU_MAX_RETRIES")? {
+        builder = builder.max_retries(max_retries);
+    }
+
+    if let Some(concurrency) = setting_or_env(args.concurrency, "OSU_CONCURRENCY")? {
+        // Every request in flight can keep its connection for reuse
+        builder = builder.concurrency(concurrency).max_idle_per_host(concurrency);
+    }
+
+    if let Some(rpm) = setting_or_env(args.rpm, "OSU_RPM")? {
+        builder = builder.rpm(rpm);
+    }
+
+    if let Some(max_requests) = args.max_requests {
+        builder = builder.max_requests(max_requests);
+    }
+
+    if let Some(dir) = &args.cache_dir {
+        builder = builder.cache(ResponseCache::new(dir, Duration::from_secs(args.cache_ttl)));
+    }
+
+    Ok(builder.build().await?)
+}
+
+/// Writes rows as they come (so a full channel slows fetching down) until
+/// every sender is gone, `on_deadline` is called once `deadline` passes
+/// and should make senders stop
+async fn drain_rows(
+    rx: &mut Receiver<Output>,
+    wtr: &mut dyn Writer,
+    mut deadline: Option<Instant>,
+    on_deadline: impl FnOnce(),
+) -> Result<usize> {
+    let mut on_deadline = Some(on_deadline);
+    let mut found = 0;
+
+    loop {
+        let next = match deadline {
+            Some(at) => tokio::time::timeout_at(at, rx.recv()).await,
+            None => Ok(rx.recv().await),
+        };
+
+        match next {
+            Ok(Some(row)) => {
+                wtr.write_row(&row)?;
+                found += 1;
+            }
+            Ok(None) => break,
+            Err(_) => {
+                deadline = None;
+
+                if let Some(f) = on_deadline.take() {
+                    f();
+                }
+            }
         }
+    }
 
-    Ok(())
+    Ok(found)
+}
+
+/// None of the processed users had scores matching the filters
+#[derive(Debug)]
+struct NoScoresFound;
+
+impl fmt::Display for NoScoresFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("no scores found for the given period and filters")
+    }
+}
+
+impl std::error::Error for NoScoresFound {}
+
+/// Process exit code for an error, 1 unless it's one of the known failures
+fn exit_code(err: &eyre::Report) -> u8 {
+    if matches!(err.downcast_ref(), Some(OsuApiError::EmptyRanking)) {
+        return 2;
+    }
+
+    if err.downcast_ref::<NoScoresFound>().is_some() {
+        return 3;
+    }
+
+    1
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+/// `--period`, otherwise deprecated `--from` and `--to`, `None` if neither is given
+fn resolve_period(args: &Args, output: &Path, date_format: &DateFormat) -> Result<Option<Period>> {
+    if args.since_last_run {
+        let now = Utc::now();
+
+        let period = match (read_last_run(&args.state_file)?, &args.period) {
+            (Some(last_run), _) => Period::new(last_run, now)?,
+            (None, Some(period)) => period.clone(),
+            (None, None) if args.append => {
+                let rows = read_rows(output)?;
+                period_since_last_run(&rows, date_format, now).ok_or_else(|| {
+                    eyre::eyre!("no readable dates in {}, pass --period instead", output.display())
+                })?
+            }
+            (None, None) => eyre::bail!(
+                "there's no {} from previous runs, pass --period for the first one",
+                args.state_file.display()
+            ),
+        };
+
+        println!("Fetching scores since last run: {}", date_format.format(&period.from));
+
+        return Ok(Some(period));
+    }
+
+    if let Some(period) = &args.period {
+        return Ok(Some(period.clone()));
+    }
+
+    let (Some(from), Some(to)) = (&args.from, &args.to) else {
+        return Ok(None);
+    };
+
+    println!("--from and --to are deprecated, use --period from:to instead");
+
+    Ok(Some(Period::new(parse_date(from)?, parse_date(to)?)?))
+}
+
+/// Period from the latest date among `rows` (it isn't included) up to `now`,
+/// `None` if there are no dates that can be read back
+fn period_since_last_run(rows: &[Output], date_format: &DateFormat, now: DateTime<Utc>) -> Option<Period> {
+    let latest = rows.iter().filter_map(|x| date_format.parse(&x.date)).max()?;
+
+    Period::new(latest, now).ok()
+}
+
+/// End of the last successful run kept in `path`, `None` if there's no such file yet
+fn read_last_run(path: &Path) -> Result<Option<DateTime<Utc>>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => match parse_last_run(&contents) {
+            Ok(last_run) => Ok(Some(last_run)),
+            Err(e) => eyre::bail!("can't read last run from {}: {e}", path.display()),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn parse_last_run(contents: &str) -> Result<DateTime<Utc>, chrono::ParseError> {
+    contents.trim().parse()
+}
+
+fn write_last_run(path: &Path, last_run: &DateTime<Utc>) -> Result<()> {
+    std::fs::write(path, format!("{}\n", last_run.to_rfc3339()))?;
+    Ok(())
+}
+
+/// Rows of previously written output that are within `period` (if it's given)
+/// and pass `filters`, rows with dates that can't be parsed back are skipped
+fn refilter(
+    rows: Vec<Output>,
+    period: Option<&Period>,
+    filters: &Filters,
+    date_format: &DateFormat,
+) -> Vec<Output> {
+    rows.into_iter()
+        .filter(|row| {
+            period.is_none_or(|period| {
+                date_format.parse(&row.date).is_some_and(|date| period.contains(&date))
+            })
+        })
+        .filter(|row| filters.matches_row(row))
+        .collect()
+}
+
+async fn run() -> Result<()> {
     let args = Args::parse();
-    dotenv()?;
 
-    let from: DateTime<Utc> = str_to_datetime!(&args.from);
-    let to: DateTime<Utc> = str_to_datetime!(&args.to);
+    // Credentials are only needed when talking to the api, so .env is optional for --input
+    if let Err(e) = dotenv() {
+        if args.input.is_none() {
+            return Err(e.into());
+        }
+    }
+
+    // Logs are quiet unless asked for with RUST_LOG e.g. RUST_LOG=debug
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"))
+        )
+        .with_writer(std::io::stderr)
+        .init();
+
+    let date_format = DateFormat::new(&args.date_format, args.utc_offset)?;
 
-    let period = Period{
-        from,
-        to
+    if args.columns.is_some() && !args.format.supports_columns() {
+        eyre::bail!("--columns is not supported by {:?} format", args.format);
+    }
+
+    if args.check {
+        build_api(&args).await?.validate().await?;
+        println!("Credentials are valid");
+        return Ok(());
+    }
+
+    if let Some(score_id) = args.score_id {
+        let api = build_api(&args).await?;
+        return print_score(
+            &api,
+            args.mode,
+            score_id,
+            args.format,
+            args.columns,
+            &date_format,
+            args.precision,
+        )
+        .await;
+    }
+
+    let path = args.output
+        .as_deref()
+        .unwrap_or(args.format.default_path());
+
+    let period = resolve_period(&args, Path::new(path), &date_format)?;
+
+    if let (Some(mods), Some(exclude)) = (args.mods, args.exclude_mods) {
+        if mods.intersects(exclude) {
+            eyre::bail!(
+                "--mods {} and --exclude-mods {} contradict each other",
+                mods.to_string(), exclude.to_string()
+            );
+        }
+    }
+
+    let filters = Filters {
+        min_score: args.min_score,
+        min_pp: args.min_pp,
+        min_accuracy: args.min_accuracy,
+        mods: args.mods,
+        exclude_mods: args.exclude_mods,
+        beatmap: args.beatmap,
+        beatmapset: args.beatmapset,
+        max_length: args.max_length,
+        min_bpm: args.min_bpm,
+        classic_only: args.classic_only,
     };
 
-    let amount = args.amount;
+    if let Some(input) = &args.input {
+        let rows = read_rows(input)?;
+        let total = rows.len();
+        let rows = refilter(rows, period.as_ref(), &filters, &date_format);
+
+        println!("{} of {total} rows passed filters", rows.len());
+
+        let wtr = open_output(Path::new(path), args.format, args.columns, args.append)?;
+        let wtr = with_precision(wtr, args.format, args.precision);
+        let mut wtr = unique_beatmaps(wtr, args.unique_beatmaps_global);
+        for row in &rows {
+            wtr.write_row(row)?;
+        }
+        wtr.finish()?;
+
+        if rows.is_empty() {
+            return Err(NoScoresFound.into());
+        }
 
-    let ranking = match args.global {
-        true => RankingType::Global,
-        false => RankingType::Country{ code: args.country.unwrap() },
+        return Ok(());
+    }
+
+    let period = period.ok_or_else(|| eyre::eyre!("--period is required"))?;
+
+    let mut window = RankWindow::new(args.from_rank, args.to_rank, args.amount)?;
+
+    let whole_ranking = window.end() == WHOLE_RANKING && args.users_file.is_none();
+
+    if whole_ranking && !args.dry_run && !args.yes {
+        let question = "--amount 0 fetches the whole leaderboard which can take thousands of requests, continue?";
+
+        if !confirm(question)? {
+            eyre::bail!("whole leaderboard wasn't confirmed, pass --yes to skip the question");
+        }
+    }
+
+    let per_user_limit = if args.per_user_limit > 100 {
+        println!(
+            "--per-user-limit {} is above the api maximum, clamping to 100",
+            args.per_user_limit
+        );
+        100
+    } else {
+        args.per_user_limit
     };
 
-    let api = Arc::new(OsuApi::new(
-        env::var("CLIENT_ID")?.parse()?,
-        env::var("CLIENT_SECRET")?.as_str(),
-        ).await?
-    );
-    
-    println!("Getting leaderboard...");
-    let users = api.get_ranking(
-        ranking,
-        (amount as f32 / 50.0).ceil() as i32
-    ).await?;
-
-    let mut output: Vec<Output> = Vec::with_capacity(amount as usize);
-
-    let (tx, mut rx) = channel(amount as usize);
-
-    tokio::spawn(fetch_thread(
-        Arc::clone(&api),
-        tx,
-        users,
-        amount as usize,
-        period
-    ));
+    let score_type = match args.score_type {
+        ScoreType::Recent { .. } => ScoreType::Recent { include_fails: args.include_fails },
+        other => other,
+    };
+
+    let api = Arc::new(build_api(&args).await?);
+
+    let users = match &args.users_file {
+        Some(path) => {
+            let lookups = parse_users_file(&std::fs::read_to_string(path)?)?;
+
+            println!("Resolving {} users...", lookups.len());
+            let users = try_join_all(lookups.iter().map(|lookup| api.get_user(args.mode, lookup))).await?;
+
+            window = RankWindow { offset: 0, count: users.len() };
+
+            users.into_iter().map(UserStatistics::from).collect()
+        },
+        None => {
+            let (ranking, warning) = ranking_type(
+                args.global,
+                args.country.clone(),
+                args.spotlight
+            )?;
+
+            if let Some(warning) = warning {
+                println!("{warning}");
+            }
+
+            if args.metric == RankingMetric::Score && ranking != RankingType::Global {
+                eyre::bail!("--metric score is only available for the global leaderboard");
+            }
+
+            println!("Getting leaderboard...");
+            let ranking = api.get_ranking(args.mode, ranking, args.metric, window.end()).await?;
+
+            if let Some(spotlight) = &ranking.spotlight {
+                println!(
+                    "Spotlight {}: {} ({} - {})",
+                    spotlight.id, spotlight.name, spotlight.start_date, spotlight.end_date
+                );
+            }
+
+            // Shortage itself is already reported by get_ranking
+            window.cap(ranking.total as usize);
+
+            if args.dry_run {
+                println!(
+                    "Dry run: fetched {} leaderboard user(s) from {} ranking page(s)",
+                    ranking.users.len(),
+                    ranking.pages
+                );
+            }
+
+            ranking.users
+        }
+    };
+
+    if args.dry_run {
+        println!("Would process:");
+
+        for (index, stats) in users
+            .iter()
+            .enumerate()
+            .skip(window.offset)
+            .take(window.count)
+        {
+            println!("#{} {} ({}pp)", index + 1, stats.user.username, stats.pp);
+        }
+
+        return Ok(());
+    }
+
+    let period_end = period.to;
+
+    let options = ScrapeOptions {
+        period,
+        filters,
+        limit: per_user_limit,
+        date_format,
+        precision: args.precision,
+        unique_beatmaps: args.unique_beatmaps,
+        check_replays: args.check_replays,
+        require_replay: args.require_replay,
+        mode: args.mode,
+        score_type,
+        max_pages: args.max_pages,
+    };
+
+    let wtr = open_output(Path::new(path), args.format, args.columns, args.append)?;
+    let wtr = with_precision(wtr, args.format, args.precision);
+    let mut wtr = unique_beatmaps(wtr, args.unique_beatmaps_global);
+
+    // Channel is bounded and a single user can have any amount of rows, so it has
+    // to be drained while fetching is in progress and not after it's done
+    let (tx, mut rx) = channel(args.channel_buffer.get());
+
+    let users: Vec<UserStatistics> = users
+        .into_iter()
+        .skip(window.offset)
+        .take(window.count)
+        .collect();
+
+    let scraper = Scraper::new(Arc::clone(&api), options);
+    let fetch = tokio::spawn(async move { scraper.run(users, tx).await });
     
-    while let Some(i) = rx.recv().await {
-        output.push(i);
+    let deadline = args.max_duration.map(|secs| Instant::now() + Duration::from_secs(secs));
+
+    let found = drain_rows(&mut rx, wtr.as_mut(), deadline, || api.stop()).await?;
+
+    if api.is_stopped() {
+        println!("Run budget is exhausted, writing collected scores");
     }
 
-    println!("Found {} scores!", output.len());
+    println!("Found {found} scores!");
 
-    let file = File::create("output.csv")?;
+    let ScrapeReport { mut summaries, failures } = fetch.await?;
+    summaries.retain(|x| x.period_weighted_pp > 0.0);
+    summaries.sort_by(|a, b| b.period_weighted_pp.total_cmp(&a.period_weighted_pp));
 
-    let mut wtr = csv::Writer::from_writer(file);
+    if !summaries.is_empty() {
+        println!("Weighted pp of in-period scores:");
 
-    for o in output {
-        wtr.serialize(o)?;
+        for summary in &summaries {
+            println!("{}: {:.2}pp", summary.username, summary.period_weighted_pp);
+        }
     }
 
-    wtr.flush()?;
+    if !failures.is_empty() {
+        let exhausted = failures.iter().filter(|x| x.retries_exhausted).count();
+        println!(
+            "{} users failed ({exhausted} after exhausting retries), errors:",
+            failures.len()
+        );
+
+        for failure in &failures {
+            println!("{} ({}): {}", failure.username, failure.user_id, failure.error);
+        }
+    }
+
+    wtr.finish()?;
+
+    // Incomplete runs keep the old state, scores that were written are
+    // deduplicated when they're appended again
+    if args.since_last_run && failures.is_empty() && !api.is_stopped() {
+        write_last_run(&args.state_file, &period_end)?;
+    }
+
+    if args.stats {
+        if let Some(stats) = api.stats() {
+            print!("{stats}");
+        }
+    }
+
+    if found == 0 {
+        return Err(NoScoresFound.into());
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::error::OsuApiError;
+    use crate::output::{read_rows, CsvWriter, Output, Writer};
+    use crate::{
+        drain_rows, exit_code, NoScoresFound, DateFormat, Filters, Period, RankWindow, ScrapeOptions, Scraper,
+        UserFailure, parse_beatmap_link, parse_country_code, parse_percent, parse_users_file,
+        period_since_last_run, period_weighted_pp, ranking_type, read_last_run, refilter,
+        unique_beatmap_scores, weighted_pp, write_last_run,
+    };
+    use crate::osu_api::{
+        GameMode, OsuMods, RankingType, Score, ScoreType, UserCompact, UserLookup, UserStatistics,
+        WHOLE_RANKING,
+    };
+    use crate::osu_api::mock;
+    use chrono::{DateTime, Utc};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    #[test]
+    fn test_date_format() {
+        let date: DateTime<Utc> = "2023-05-15T22:32:01Z".parse().unwrap();
+
+        let format = DateFormat::new("%Y-%m-%d %H:%M:%S", 0).unwrap();
+        assert_eq!(format.format(&date), "2023-05-15 22:32:01");
+
+        let format = DateFormat::new("%d/%m/%Y", 3).unwrap();
+        assert_eq!(format.format(&date), "16/05/2023");
+
+        let format = DateFormat::new("%s", -5).unwrap();
+        assert_eq!(format.format(&date), "1684189921");
+
+        assert!(DateFormat::new("%Q", 0).is_err());
+        assert!(DateFormat::new("%Y", 30).is_err());
+        assert!(DateFormat::new("%Y", 1_000_000).is_err());
+        assert!(DateFormat::new("%Y", i32::MIN).is_err());
+    }
+
+    fn score(pp: f32, created_at: &str) -> Score {
+        serde_json::from_value(score_json(1, pp, created_at, false)).unwrap()
+    }
+
+    fn score_json(id: i64, pp: f32, created_at: &str, replay: bool) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "best_id": id,
+            "user_id": 1,
+            "accuracy": 0.99,
+            "mods": [],
+            "score": 1000000,
+            "pp": pp,
+            "created_at": created_at,
+            "replay": replay,
+            "beatmapset": {
+                "id": 1,
+                "artist": "",
+                "artist_unicode": "",
+                "creator": "",
+                "source": "",
+                "title": "",
+                "title_unicode": ""
+            },
+            "beatmap": {
+                "id": 1,
+                "version": ""
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_scraper_run() {
+        let api = mock::api(|path| {
+            let scores = match path {
+                p if p.starts_with("/api/v2/users/1/scores/") => serde_json::json!([
+                    score_json(11, 300.0, "2023-05-10T00:00:00Z", true),
+                    score_json(12, 250.0, "2023-04-10T00:00:00Z", false),
+                    score_json(13, 200.0, "2023-05-20T00:00:00Z", false),
+                ]),
+                // Replay check keeps failing
+                "/api/v2/scores/osu/11/download" => return (502, r#"{"error":"bad gateway"}"#.to_owned()),
+                _ => return (500, r#"{"error":"boom"}"#.to_owned()),
+            };
+
+            (200, scores.to_string())
+        })
+        .await;
+        let api = Arc::new(api);
+
+        let users: Vec<UserStatistics> = serde_json::from_str(r#"[
+            { "pp": 10000.0, "user": { "id": 1, "username": "someone" } },
+            { "pp": 9000.0, "user": { "id": 2, "username": "broken" } }
+        ]"#).unwrap();
+
+        let options = |score_type| ScrapeOptions {
+            period: "2023-05-01:2023-06-01".parse().unwrap(),
+            filters: Filters::default(),
+            limit: 100,
+            date_format: DateFormat::new("%Y-%m-%d", 0).unwrap(),
+            precision: 2,
+            unique_beatmaps: false,
+            check_replays: true,
+            require_replay: false,
+            mode: GameMode::Osu,
+            score_type,
+            max_pages: None,
+        };
+
+        let run = |score_type| {
+            let scraper = Scraper::new(Arc::clone(&api), options(score_type));
+            let users = users.clone();
+
+            async move {
+                let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+                let report = scraper.run(users, tx).await;
+
+                let mut rows = Vec::new();
+                while let Some(row) = rx.recv().await {
+                    rows.push(row);
+                }
+
+                (rows, report)
+            }
+        };
+
+        let (rows, report) = run(ScoreType::Best).await;
+
+        // Score out of period is skipped, failed replay check doesn't drop the row
+        let links: Vec<&str> = rows.iter().map(|x| x.score_link.as_str()).collect();
+        assert_eq!(links, vec!["https://osu.ppy.sh/scores/11", "https://osu.ppy.sh/scores/13"]);
+        assert_eq!(rows[0].replay_available, None);
+        assert_eq!(rows[1].replay_available, Some(false));
+        assert_eq!(rows[1].weighted_pp, Some(weighted_pp(&score(200.0, "2023-05-20T00:00:00Z"), 2)));
+
+        assert_eq!(report.summaries.len(), 1);
+        assert_eq!(report.summaries[0].username, "someone");
+        assert_eq!(report.summaries[0].period_weighted_pp, 300.0 + 200.0 * 0.95 * 0.95);
+
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].username, "broken");
+        assert_eq!(report.failures[0].user_id, 2);
+        assert!(!report.failures[0].retries_exhausted);
+
+        // Positions mean nothing outside of best scores
+        let (rows, report) = run(ScoreType::Recent { include_fails: false }).await;
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|x| x.weighted_pp.is_none()));
+        assert!(report.summaries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_drain_rows_deadline() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // Endless sender that only stops once asked to
+        let sender_stop = Arc::clone(&stop);
+        tokio::spawn(async move {
+            while !sender_stop.load(Ordering::Relaxed) {
+                let row = Output { username: "abc".to_owned(), ..Default::default() };
+                if tx.send(row).await.is_err() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        });
+
+        let mut buf = Vec::new();
+        let deadline = Instant::now() + Duration::from_millis(100);
+
+        let found = {
+            let mut wtr = CsvWriter::new(&mut buf, None, true);
+            let found = drain_rows(&mut rx, &mut wtr, Some(deadline), || {
+                stop.store(true, Ordering::Relaxed)
+            })
+            .await
+            .unwrap();
+            Box::new(wtr).finish().unwrap();
+            found
+        };
+
+        assert!(stop.load(Ordering::Relaxed));
+        assert!(found > 0);
+
+        let content = String::from_utf8(buf).unwrap();
+        assert_eq!(content.lines().count(), found + 1);
+        assert!(content.starts_with("username,"));
+    }
+
+    #[tokio::test]
+    async fn test_drain_rows_small_buffer() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel(2);
+
+        // Single producer with far more rows than channel can hold
+        let producer = tokio::spawn(async move {
+            for i in 0..1000 {
+                let row = Output { username: format!("user{i}"), ..Default::default() };
+                tx.send(row).await.unwrap();
+            }
+        });
+
+        let mut buf = Vec::new();
+        let mut wtr = CsvWriter::new(&mut buf, None, true);
+
+        let found = tokio::time::timeout(
+            Duration::from_secs(5),
+            drain_rows(&mut rx, &mut wtr, None, || ()),
+        )
+        .await
+        .expect("rows should be drained without deadlock")
+        .unwrap();
+        Box::new(wtr).finish().unwrap();
+
+        producer.await.unwrap();
+        assert_eq!(found, 1000);
+
+        let content = String::from_utf8(buf).unwrap();
+        let usernames: Vec<&str> = content
+            .lines()
+            .skip(1)
+            .map(|x| x.split(',').next().unwrap())
+            .collect();
+        assert_eq!(usernames.len(), 1000);
+        assert_eq!(usernames[0], "user0");
+        assert_eq!(usernames[999], "user999");
+    }
+
+    #[test]
+    fn test_user_failure() {
+        let user: UserCompact = serde_json::from_str(r#"{"id": 6892711, "username": "abc"}"#).unwrap();
+
+        let failure = UserFailure::new(&user, &OsuApiError::Timeout.into());
+        assert_eq!(failure.username, "abc");
+        assert_eq!(failure.user_id, 6892711);
+        assert_eq!(failure.error, "request timed out!");
+        assert!(failure.retries_exhausted);
+
+        let failure = UserFailure::new(&user, &OsuApiError::BadRequest.into());
+        assert!(!failure.retries_exhausted);
+
+        let failure = UserFailure::new(&user, &eyre::eyre!("writer is gone"));
+        assert_eq!(failure.error, "writer is gone");
+        assert!(!failure.retries_exhausted);
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(&OsuApiError::EmptyRanking.into()), 2);
+        assert_eq!(exit_code(&NoScoresFound.into()), 3);
+        assert_eq!(exit_code(&OsuApiError::Timeout.into()), 1);
+        assert_eq!(exit_code(&eyre::eyre!("something else")), 1);
+
+        // Context doesn't hide the original error
+        let err = eyre::Report::from(OsuApiError::EmptyRanking).wrap_err("fetching leaderboard");
+        assert_eq!(exit_code(&err), 2);
+    }
+
+    #[test]
+    fn test_period_from_str() {
+        let period: Period = "01-05-2023:2023-05-31".parse().unwrap();
+        assert_eq!(period.from, "2023-05-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap());
+        assert_eq!(period.to, "2023-05-31T00:00:00Z".parse::<DateTime<Utc>>().unwrap());
+
+        assert!("01-05-2023".parse::<Period>().unwrap_err().contains("from:to"));
+        assert!("bad:31-05-2023".parse::<Period>().unwrap_err().contains("start"));
+        assert!("01-05-2023:bad".parse::<Period>().unwrap_err().contains("end"));
+        assert!("31-05-2023:01-05-2023".parse::<Period>().unwrap_err().contains("before"));
+        assert!("01-05-2023:01-05-2023".parse::<Period>().is_err());
+    }
+
+    #[test]
+    fn test_period_weighted_pp() {
+        let period = Period {
+            from: "2023-05-01T00:00:00Z".parse().unwrap(),
+            to: "2023-06-01T00:00:00Z".parse().unwrap(),
+        };
+
+        let scores = vec![
+            score(400.0, "2023-05-10T00:00:00Z"),
+            score(300.0, "2022-01-01T00:00:00Z"),
+            score(200.0, "2023-05-20T00:00:00Z"),
+        ];
+
+        // 400 * 0.95^0 + 200 * 0.95^2
+        let weighted = period_weighted_pp(&scores, &period);
+        assert!((weighted - 580.5).abs() < 0.01);
+
+        assert_eq!(period_weighted_pp(&scores[1..2], &period), 0.0);
+        assert_eq!(period_weighted_pp(&[], &period), 0.0);
+    }
+
+    #[test]
+    fn test_weighted_pp() {
+        let scores: Vec<Score> = [400.0, 300.0, 200.0, 100.0]
+            .into_iter()
+            .map(|pp| score(pp, "2023-05-10T00:00:00Z"))
+            .collect();
+
+        let weighted: Vec<f32> = scores
+            .iter()
+            .enumerate()
+            .map(|(i, x)| weighted_pp(x, i))
+            .collect();
+
+        // 400 * 1, 300 * 0.95, 200 * 0.9025, 100 * 0.857375
+        for (actual, expected) in weighted.iter().zip([400.0, 285.0, 180.5, 85.7375]) {
+            assert!((actual - expected).abs() < 0.01, "{actual} != {expected}");
+        }
+
+        let mut unranked = score(0.0, "2023-05-10T00:00:00Z");
+        unranked.pp = None;
+        assert_eq!(weighted_pp(&unranked, 0), 0.0);
+    }
+
+    #[test]
+    fn test_ranking_type() {
+        let codes = |x: &[&str]| x.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+
+        let (ranking, warning) = ranking_type(true, codes(&["BY"]), None).unwrap();
+        assert_eq!(ranking, RankingType::Global);
+        assert!(warning.unwrap().contains("BY"));
+
+        let (ranking, warning) = ranking_type(true, vec![], None).unwrap();
+        assert_eq!(ranking, RankingType::Global);
+        assert!(warning.is_none());
+
+        let (ranking, warning) = ranking_type(false, codes(&["BY"]), None).unwrap();
+        assert_eq!(ranking, RankingType::Country { code: "BY".to_owned() });
+        assert!(warning.is_none());
+
+        // Duplicates are dropped, single remaining code is a plain country leaderboard
+        let (ranking, _) = ranking_type(false, codes(&["BY", "BY"]), None).unwrap();
+        assert_eq!(ranking, RankingType::Country { code: "BY".to_owned() });
+
+        let (ranking, _) = ranking_type(false, codes(&["RU", "BY", "RU"]), None).unwrap();
+        assert_eq!(ranking, RankingType::Countries { codes: codes(&["BY", "RU"]) });
+
+        let (ranking, _) = ranking_type(false, vec![], Some(271)).unwrap();
+        assert_eq!(ranking, RankingType::Spotlight { id: 271 });
+
+        assert!(ranking_type(false, vec![], None).is_err());
+    }
+
+    #[test]
+    fn test_filters_mods() {
+        let filters = Filters {
+            min_score: 0,
+            min_accuracy: 0.0,
+            mods: Some("HD".parse().unwrap()),
+            exclude_mods: Some("EZHTNF".parse().unwrap()),
+            ..Default::default()
+        };
+
+        assert!(filters.matches_mods(OsuMods::HIDDEN));
+        assert!(filters.matches_mods(OsuMods::HIDDEN | OsuMods::DOUBLETIME));
+        assert!(!filters.matches_mods(OsuMods::DOUBLETIME));
+        assert!(!filters.matches_mods(OsuMods::HIDDEN | OsuMods::NOFAIL));
+        assert!(!filters.matches_mods(OsuMods::HIDDEN | OsuMods::HALFTIME));
+
+        let filters = Filters {
+            min_score: 0,
+            min_accuracy: 0.0,
+            mods: None,
+            exclude_mods: None,
+            ..Default::default()
+        };
+
+        assert!(filters.matches_mods(OsuMods::EASY));
+        assert!(filters.matches_mods(OsuMods::empty()));
+    }
+
+    #[test]
+    fn test_filters_map() {
+        let scores: Vec<Score> = [(10, 100), (11, 100), (20, 200)]
+            .into_iter()
+            .map(|(map, set)| {
+                let mut score = score(100.0, "2023-05-01T00:00:00Z");
+                score.beatmap.id = map;
+                score.beatmapset.id = set;
+                score
+            })
+            .collect();
+
+        let matching = |filters: Filters| -> Vec<i64> {
+            scores
+                .iter()
+                .filter(|x| filters.matches_map(x))
+                .map(|x| x.beatmap.id)
+                .collect()
+        };
+
+        assert_eq!(matching(Filters::default()), vec![10, 11, 20]);
+        assert_eq!(matching(Filters { beatmapset: Some(100), ..Default::default() }), vec![10, 11]);
+        assert_eq!(matching(Filters { beatmap: Some(11), ..Default::default() }), vec![11]);
+        assert_eq!(
+            matching(Filters { beatmap: Some(11), beatmapset: Some(200), ..Default::default() }),
+            Vec::<i64>::new()
+        );
+    }
+
+    #[test]
+    fn test_filters_length_bpm() {
+        let scores: Vec<Score> = [(10, 90, 180.0), (11, 420, 220.0), (12, 120, 240.0)]
+            .into_iter()
+            .map(|(map, length, bpm)| {
+                let mut score = score(100.0, "2023-05-01T00:00:00Z");
+                score.beatmap.id = map;
+                score.beatmap.total_length = length;
+                score.beatmap.bpm = bpm;
+                score
+            })
+            .collect();
+
+        let matching = |filters: Filters| -> Vec<i64> {
+            scores
+                .iter()
+                .filter(|x| filters.matches_map(x))
+                .map(|x| x.beatmap.id)
+                .collect()
+        };
+
+        assert_eq!(matching(Filters { max_length: Some(120), ..Default::default() }), vec![10, 12]);
+        assert_eq!(matching(Filters { min_bpm: Some(220.0), ..Default::default() }), vec![11, 12]);
+        assert_eq!(
+            matching(Filters { max_length: Some(120), min_bpm: Some(200.0), ..Default::default() }),
+            vec![12]
+        );
+
+        // Fixtures without the fields still parse
+        assert_eq!(score(100.0, "2023-05-01T00:00:00Z").beatmap.total_length, 0);
+    }
+
+    #[test]
+    fn test_unique_beatmap_scores() {
+        let on_map = |pp: f32, map: i64| {
+            let mut score = score(pp, "2023-05-15T00:00:00Z");
+            score.beatmap.id = map;
+            score
+        };
+
+        let mut unranked = on_map(0.0, 3);
+        unranked.pp = None;
+
+        let scores = vec![on_map(300.0, 1), on_map(250.0, 2), on_map(280.0, 2), on_map(200.0, 1), unranked];
+
+        let unique: Vec<(usize, f32)> = unique_beatmap_scores(&scores)
+            .into_iter()
+            .map(|(index, score)| (index, score.pp.unwrap_or(0.0)))
+            .collect();
+
+        // Positions are the ones in the full list, they matter for weighted pp
+        assert_eq!(unique, vec![(0, 300.0), (2, 280.0), (4, 0.0)]);
+    }
+
+    #[test]
+    fn test_scrape_options_accepts() {
+        let options = ScrapeOptions {
+            period: "2023-05-01:2023-06-01".parse().unwrap(),
+            filters: Filters {
+                min_pp: 50.0,
+                min_accuracy: 95.0,
+                exclude_mods: Some(OsuMods::EASY),
+                classic_only: true,
+                ..Default::default()
+            },
+            limit: 100,
+            date_format: DateFormat::new("%Y-%m-%d", 0).unwrap(),
+            precision: 2,
+            unique_beatmaps: false,
+            check_replays: false,
+            require_replay: false,
+            mode: GameMode::Osu,
+            score_type: ScoreType::Best,
+            max_pages: None,
+        };
+
+        let in_period = || score(100.0, "2023-05-15T00:00:00Z");
+        assert!(options.accepts(&in_period()));
+
+        assert!(!options.accepts(&score(100.0, "2023-06-15T00:00:00Z")));
+
+        let mut inaccurate = in_period();
+        inaccurate.accuracy = 0.9;
+        assert!(!options.accepts(&inaccurate));
+
+        let mut excluded = in_period();
+        excluded.mods = OsuMods::EASY | OsuMods::HIDDEN;
+        assert!(!options.accepts(&excluded));
+
+        let mut scorev2 = in_period();
+        scorev2.mods = OsuMods::SCOREV2;
+        assert!(!options.accepts(&scorev2));
+
+        assert!(!options.accepts(&score(40.0, "2023-05-15T00:00:00Z")));
+
+        let mut unranked = in_period();
+        unranked.pp = None;
+        assert!(!options.accepts(&unranked));
+
+        let mut failed = in_period();
+        failed.passed = false;
+        assert!(!options.accepts(&failed));
+
+        let with_fails = ScrapeOptions {
+            score_type: ScoreType::Recent { include_fails: true },
+            ..options
+        };
+        assert!(with_fails.accepts(&failed));
+    }
+
+    #[test]
+    fn test_refilter() {
+        let fixture = [
+            r#"{"username":"a","pp":300.5,"accuracy":"99.10%","total_score":1000,"date":"2023-05-10 12:00:00","mods":"HDDT","beatmap_link":"https://osu.ppy.sh/beatmapsets/1#osu/10","scorev2":false}"#,
+            r#"{"username":"b","pp":null,"accuracy":"97.00%","total_score":500,"date":"2023-05-11 12:00:00","mods":"NM","beatmap_link":"https://osu.ppy.sh/beatmapsets/2#osu/20","scorev2":false}"#,
+            r#"{"username":"c","pp":250.0,"accuracy":"98.00%","total_score":900,"date":"2023-06-20 12:00:00","mods":"HD","beatmap_link":"https://osu.ppy.sh/beatmapsets/1#osu/11","scorev2":false}"#,
+            r#"{"username":"d","pp":400.0,"accuracy":"96.00%","total_score":800,"date":"2023-05-12 12:00:00","mods":"HDV2","beatmap_link":"https://osu.ppy.sh/beatmapsets/3#osu/30","scorev2":true}"#,
+        ];
+
+        let path = std::env::temp_dir().join(format!("scrapper-refilter-{}.jsonl", std::process::id()));
+        std::fs::write(&path, fixture.join("\n")).unwrap();
+        let rows = read_rows(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let date_format = DateFormat::new("%Y-%m-%d %H:%M:%S", 0).unwrap();
+        let period: Period = "2023-05-01:2023-06-01".parse().unwrap();
+
+        let usernames = |filters: Filters, period: Option<&Period>| -> Vec<String> {
+            refilter(rows.clone(), period, &filters, &date_format)
+                .into_iter()
+                .map(|x| x.username)
+                .collect()
+        };
+
+        assert_eq!(usernames(Filters::default(), None), vec!["a", "b", "c", "d"]);
+        assert_eq!(usernames(Filters::default(), Some(&period)), vec!["a", "b", "d"]);
+        assert_eq!(usernames(Filters { min_pp: 1.0, ..Default::default() }, None), vec!["a", "c", "d"]);
+        assert_eq!(
+            usernames(Filters { mods: Some(OsuMods::HIDDEN), classic_only: true, ..Default::default() }, None),
+            vec!["a", "c"]
+        );
+        assert_eq!(usernames(Filters { min_accuracy: 97.5, ..Default::default() }, None), vec!["a", "c"]);
+        assert_eq!(usernames(Filters { beatmapset: Some(1), ..Default::default() }, Some(&period)), vec!["a"]);
+        assert_eq!(usernames(Filters { min_score: 850, ..Default::default() }, None), vec!["a", "c"]);
+
+        assert_eq!(parse_beatmap_link("https://osu.ppy.sh/beatmapsets/77#mania/88"), Some((77, 88)));
+        assert_eq!(parse_beatmap_link(""), None);
+
+        // Date only formats are read back as midnight in given offset
+        let date_format = DateFormat::new("%d.%m.%Y", 3).unwrap();
+        let expected: DateTime<Utc> = "2023-05-09T21:00:00Z".parse().unwrap();
+        assert_eq!(date_format.parse("10.05.2023"), Some(expected));
+        assert_eq!(DateFormat::new("%Y", 0).unwrap().parse("2023"), None);
+    }
+
+    #[test]
+    fn test_period_since_last_run() {
+        let date_format = DateFormat::new("%Y-%m-%d %H:%M:%S", 0).unwrap();
+        let now: DateTime<Utc> = "2023-06-01T00:00:00Z".parse().unwrap();
+
+        let rows: Vec<Output> = ["2023-05-10 12:00:00", "2023-05-20 08:30:00", "garbage", "2023-05-01 00:00:00"]
+            .into_iter()
+            .map(|date| Output { date: date.to_owned(), ..Default::default() })
+            .collect();
+
+        let period = period_since_last_run(&rows, &date_format, now).unwrap();
+        assert_eq!(period.from, "2023-05-20T08:30:00Z".parse::<DateTime<Utc>>().unwrap());
+        assert_eq!(period.to, now);
+
+        assert!(period_since_last_run(&rows[2..3], &date_format, now).is_none());
+        assert!(period_since_last_run(&[], &date_format, now).is_none());
+    }
+
+    #[test]
+    fn test_last_run_state() {
+        let path = std::env::temp_dir().join(format!("scrapper-last-run-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert!(read_last_run(&path).unwrap().is_none());
+
+        let last_run: DateTime<Utc> = "2023-05-20T08:30:00Z".parse().unwrap();
+        write_last_run(&path, &last_run).unwrap();
+        let stored = read_last_run(&path).unwrap().unwrap();
+        assert_eq!(stored, last_run);
+
+        let now: DateTime<Utc> = "2023-05-21T08:00:00Z".parse().unwrap();
+        let period = Period::new(stored, now).unwrap();
+        assert_eq!(period.from, last_run);
+        assert_eq!(period.to, now);
+
+        std::fs::write(&path, "yesterday").unwrap();
+        let err = read_last_run(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.contains("can't read last run"));
+    }
+
+    #[test]
+    fn test_parse_users_file() {
+        let users = parse_users_file("6892711\n\n  peppy \nsome user_[1]\n").unwrap();
+
+        assert_eq!(users, vec![
+            UserLookup::Id(6892711),
+            UserLookup::Username("peppy".to_owned()),
+            UserLookup::Username("some user_[1]".to_owned()),
+        ]);
+
+        let err = parse_users_file("123\nbad!name\nthis-name-is-way-too-long").unwrap_err();
+        let msg = err.to_string();
+
+        assert!(msg.contains("line 2"));
+        assert!(msg.contains("line 3"));
+        assert!(!msg.contains("line 1"));
+
+        assert!(parse_users_file("\n\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("95.5").unwrap(), 95.5);
+        assert_eq!(parse_percent("0").unwrap(), 0.0);
+        assert_eq!(parse_percent("100").unwrap(), 100.0);
+
+        assert!(parse_percent("100.1").is_err());
+        assert!(parse_percent("-1").is_err());
+        assert!(parse_percent("NaN").is_err());
+        assert!(parse_percent("abc").is_err());
+    }
+
+    #[test]
+    fn test_parse_country_code() {
+        assert_eq!(parse_country_code("by").unwrap(), "BY");
+        assert_eq!(parse_country_code("By").unwrap(), "BY");
+        assert_eq!(parse_country_code("US").unwrap(), "US");
+
+        assert!(parse_country_code("USA").is_err());
+        assert_eq!(
+            parse_country_code("zz").unwrap_err(),
+            "ZZ is not a recognized country code"
+        );
+        assert!(parse_country_code("1B").is_err());
+        assert!(parse_country_code("").is_err());
+    }
+
+    #[test]
+    fn test_rank_window() {
+        let window = RankWindow::new(None, None, 200).unwrap();
+        assert_eq!(window, RankWindow { offset: 0, count: 200 });
+        assert_eq!(window.end(), 200);
+
+        let window = RankWindow::new(Some(500), Some(1000), 200).unwrap();
+        assert_eq!(window, RankWindow { offset: 499, count: 501 });
+        assert_eq!(window.end(), 1000);
+
+        let window = RankWindow::new(Some(51), None, 1).unwrap();
+        assert_eq!(window.end(), 51);
+
+        let window = RankWindow::new(None, Some(49), 200).unwrap();
+        assert_eq!(window.end(), 49);
+
+        let mut window = RankWindow::new(Some(11), None, 0).unwrap();
+        assert_eq!(window.end(), WHOLE_RANKING);
+        window.cap(1234);
+        assert_eq!(window, RankWindow { offset: 10, count: 1224 });
+        assert_eq!(RankWindow::new(None, Some(49), 0).unwrap().end(), 49);
+
+        let mut window = RankWindow::new(None, None, 200).unwrap();
+        window.cap(142);
+        assert_eq!(window.count, 142);
+
+        let mut window = RankWindow::new(Some(150), None, 200).unwrap();
+        window.cap(142);
+        assert_eq!(window.count, 0);
+
+        assert!(RankWindow::new(Some(0), None, 200).is_err());
+        assert!(RankWindow::new(Some(100), Some(50), 200).is_err());
+
+        let err = RankWindow::new(Some(5), None, usize::MAX - 3).unwrap_err().to_string();
+        assert!(err.contains("--amount"), "{err}");
+        assert!(RankWindow::new(Some(1), None, usize::MAX).is_ok());
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Durations and outcomes of every api request made during the run
+#[derive(Debug, Default)]
+pub struct RequestMetrics {
+    durations: Vec<Duration>,
+    outcomes: BTreeMap<String, usize>,
+    recovered: usize,
+    exhausted: usize,
+}
+
+impl RequestMetrics {
+    /// `outcome` is response status code or failure kind e.g. timeout
+    pub fn record(&mut self, outcome: impl Into<String>, elapsed: Duration) {
+        self.durations.push(elapsed);
+        *self.outcomes.entry(outcome.into()).or_default() += 1;
+    }
+
+    /// Final result of request that was retried at least once
+    pub fn record_retried(&mut self, succeeded: bool) {
+        match succeeded {
+            true => self.recovered += 1,
+            false => self.exhausted += 1,
+        }
+    }
+
+    /// `None` if no requests were made
+    pub fn summary(&self) -> Option<MetricsSummary> {
+        if self.durations.is_empty() {
+            return None;
+        }
+
+        let mut sorted = self.durations.clone();
+        sorted.sort();
+
+        let total = sorted.len();
+        let p95_index = ((total as f64 * 0.95).ceil() as usize).clamp(1, total) - 1;
+
+        Some(MetricsSummary {
+            total,
+            mean: sorted.iter().sum::<Duration>() / total as u32,
+            p95: sorted[p95_index],
+            outcomes: self.outcomes.clone(),
+            recovered: self.recovered,
+            exhausted: self.exhausted,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MetricsSummary {
+    pub total: usize,
+    pub mean: Duration,
+    pub p95: Duration,
+    pub outcomes: BTreeMap<String, usize>,
+    /// Requests that succeeded after being retried
+    pub recovered: usize,
+    /// Requests that failed even after being retried
+    pub exhausted: usize,
+}
+
+impl fmt::Display for MetricsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Requests: {}", self.total)?;
+        writeln!(f, "Mean latency: {:.2?}", self.mean)?;
+        writeln!(f, "p95 latency: {:.2?}", self.p95)?;
+
+        for (outcome, count) in &self.outcomes {
+            writeln!(f, "{outcome}: {count}")?;
+        }
+
+        writeln!(f, "Succeeded after retries: {}", self.recovered)?;
+        writeln!(f, "Failed after retries: {}", self.exhausted)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::RequestMetrics;
+    use std::time::Duration;
+
+    #[test]
+    fn test_summary() {
+        let mut metrics = RequestMetrics::default();
+        assert!(metrics.summary().is_none());
+
+        for ms in 1..=20 {
+            metrics.record("200", Duration::from_millis(ms * 10));
+        }
+        metrics.record("timeout", Duration::from_millis(1000));
+        metrics.record_retried(true);
+        metrics.record_retried(true);
+        metrics.record_retried(false);
+
+        let summary = metrics.summary().unwrap();
+        assert_eq!(summary.total, 21);
+        assert_eq!(summary.mean, Duration::from_millis(3100) / 21);
+        assert_eq!(summary.p95, Duration::from_millis(200));
+        assert_eq!(summary.outcomes["200"], 20);
+        assert_eq!(summary.outcomes["timeout"], 1);
+        assert_eq!(summary.recovered, 2);
+        assert_eq!(summary.exhausted, 1);
+    }
+}
@@ -1,8 +1,11 @@
 use hyper::{
     body::Bytes,
     client::{Client, HttpConnector},
-    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, USER_AGENT},
-    Body, Method, Request, StatusCode,
+    header::{
+        AsHeaderName, HeaderMap, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING,
+        CONTENT_TYPE, RETRY_AFTER, USER_AGENT,
+    },
+    Body, Method, Request, Response, StatusCode,
 };
 use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 
@@ -11,17 +14,57 @@ use serde::de::DeserializeOwned;
 use serde::de::{Deserializer, Error, SeqAccess, Unexpected, Visitor};
 use serde::Deserialize;
 
+use crate::cache::ResponseCache;
 use crate::error::{ApiErrorResponse, OsuApiError};
+use crate::metrics::{MetricsSummary, RequestMetrics};
+use crate::util::cut;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fmt::Write;
+use std::future::Future;
+use std::io::Read;
+use std::num::NonZeroU32;
+use std::ops::RangeInclusive;
 use std::str::FromStr;
-use std::string::ToString;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use chrono::{DateTime, NaiveDateTime, Utc};
 
 use bitflags::bitflags;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use futures::future::{self, try_join_all};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+pub type ApiResult<T> = Result<T, OsuApiError>;
+
+const DEFAULT_BASE_URL: &str = "https://osu.ppy.sh";
+const DEFAULT_USER_AGENT: &str = "vasteri-bebrik";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+const RANKING_PAGES_CONCURRENCY: usize = 4;
+const RANKING_PAGE_SIZE: usize = 50;
+const USER_SCORES_PAGE_SIZE: u32 = 100;
+
+/// `get_ranking` limit that covers the whole leaderboard
+pub const WHOLE_RANKING: usize = usize::MAX;
+
+/// Best scores are always passed, so missing `passed` means it is
+fn default_passed() -> bool {
+    true
+}
 
-type ApiResult<T> = Result<T, OsuApiError>;
+/// `null` (unranked players) is read as 0
+fn null_as_zero<'de, D: de::Deserializer<'de>>(d: D) -> Result<f32, D::Error> {
+    Ok(Option::<f32>::deserialize(d)?.unwrap_or_default())
+}
 
 pub fn deserialize_utc_datetime<'de, D>(d: D) -> Result<DateTime<Utc>, D::Error>
 where
@@ -50,27 +93,33 @@ where
     d.deserialize_str(LocalDateTimeVisitor)
 }
 
-pub fn cut(mut source: &str, n: usize) -> impl Iterator<Item = &str> {
-    std::iter::from_fn(move || {
-        if source.is_empty() {
-            None
-        } else {
-            let end_idx = source
-                .char_indices()
-                .nth(n - 1)
-                .map_or_else(|| source.len(), |(idx, c)| idx + c.len_utf8());
-
-            let (split, rest) = source.split_at(end_idx);
-
-            source = rest;
+/// Numeric header value, `None` if header is missing or malformed
+fn header_value<K: AsHeaderName, T: FromStr>(headers: &HeaderMap, key: K) -> Option<T> {
+    headers.get(key)?.to_str().ok()?.trim().parse().ok()
+}
 
-            Some(split)
-        }
-    })
+/// Runs `f` for every page with at most `concurrency` of them in flight,
+/// results are returned in the same order as pages. Stops at the first
+/// empty page, pages after it that are already in flight are dropped
+pub async fn fetch_ordered<T, F, Fut>(
+    pages: RangeInclusive<i32>,
+    concurrency: usize,
+    f: F,
+) -> ApiResult<Vec<Vec<T>>>
+where
+    F: FnMut(i32) -> Fut,
+    Fut: Future<Output = ApiResult<Vec<T>>>,
+{
+    stream::iter(pages)
+        .map(f)
+        .buffered(concurrency)
+        .try_take_while(|page| future::ready(Ok(!page.is_empty())))
+        .try_collect()
+        .await
 }
 
 bitflags! {
-    #[derive(Default, Debug)]
+    #[derive(Default, Debug, Clone, Copy, PartialEq)]
     pub struct OsuMods: u32 {
         const NOMOD = 0;
         const NOFAIL = 1;
@@ -92,88 +141,124 @@ bitflags! {
     }
 }
 
-impl ToString for OsuMods {
-    fn to_string(&self) -> String {
-        let mut res = String::new();
+impl TryFrom<u32> for OsuMods {
+    type Error = OsuApiError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        OsuMods::from_bits(value).ok_or(OsuApiError::UnknownModBits(value))
+    }
+}
 
+/// Display order used by the game client (follows bit order), combined
+/// mods come before their base mods so NC/PF replace DT/SD
+const MODS_DISPLAY_ORDER: [(OsuMods, &str); 16] = [
+    (OsuMods::NOFAIL, "NF"),
+    (OsuMods::EASY, "EZ"),
+    (OsuMods::TOUCHDEVICE, "TD"),
+    (OsuMods::HIDDEN, "HD"),
+    (OsuMods::HARDROCK, "HR"),
+    (OsuMods::PERFECT, "PF"),
+    (OsuMods::SUDDENDEATH, "SD"),
+    (OsuMods::NIGHTCORE, "NC"),
+    (OsuMods::DOUBLETIME, "DT"),
+    (OsuMods::RELAX, "RX"),
+    (OsuMods::HALFTIME, "HT"),
+    (OsuMods::FLASHLIGHT, "FL"),
+    (OsuMods::SPUNOUT, "SO"),
+    (OsuMods::FADEIN, "FD"),
+    (OsuMods::SCOREV2, "V2"),
+    (OsuMods::MIRROR, "MR"),
+];
+
+impl fmt::Display for OsuMods {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.is_empty() {
-            res.push_str("NM");
-            return res;
+            return f.write_str("NM");
         }
 
-        if self.contains(OsuMods::NOFAIL) {
-            res.push_str("NF")
-        }
-        if self.contains(OsuMods::EASY) {
-            res.push_str("EZ")
-        }
-        if self.contains(OsuMods::TOUCHDEVICE) {
-            res.push_str("TD")
-        }
-        if self.contains(OsuMods::HIDDEN) {
-            res.push_str("HD")
-        }
-        if self.contains(OsuMods::DOUBLETIME) {
-            if self.contains(OsuMods::NIGHTCORE) {
-                res.push_str("NC")
-            } else {
-                res.push_str("DT")
+        let mut left = *self;
+
+        for (mods, abbrev) in MODS_DISPLAY_ORDER {
+            if left.contains(mods) {
+                f.write_str(abbrev)?;
+                left.remove(mods);
             }
         }
-        if self.contains(OsuMods::HALFTIME) {
-            res.push_str("HT")
-        }
-        if self.contains(OsuMods::FLASHLIGHT) {
-            res.push_str("FL")
-        }
-        if self.contains(OsuMods::HARDROCK) {
-            res.push_str("HR")
-        }
-        if self.contains(OsuMods::SUDDENDEATH) {
-            res.push_str("SD")
-        }
-        if self.contains(OsuMods::SPUNOUT) {
-            res.push_str("SO")
+
+        Ok(())
+    }
+}
+
+impl OsuMods {
+    /// Speed the map is played at, NC counts as DT
+    pub fn clock_rate(&self) -> f32 {
+        if self.contains(OsuMods::DOUBLETIME) {
+            1.5
+        } else if self.contains(OsuMods::HALFTIME) {
+            0.75
+        } else {
+            1.0
         }
-        if self.contains(OsuMods::PERFECT) {
-            res.push_str("PF")
+    }
+
+    /// Mod (or `NOMOD`) for a single uppercase acronym
+    fn from_acronym(acronym: &str) -> Option<OsuMods> {
+        let mods = match acronym {
+            "NM" => OsuMods::NOMOD,
+            "NF" => OsuMods::NOFAIL,
+            "EZ" => OsuMods::EASY,
+            "TD" => OsuMods::TOUCHDEVICE,
+            "HD" => OsuMods::HIDDEN,
+            "HR" => OsuMods::HARDROCK,
+            "SD" => OsuMods::SUDDENDEATH,
+            "DT" => OsuMods::DOUBLETIME,
+            "RX" => OsuMods::RELAX,
+            "HT" => OsuMods::HALFTIME,
+            "NC" => OsuMods::NIGHTCORE,
+            "FL" => OsuMods::FLASHLIGHT,
+            "SO" => OsuMods::SPUNOUT,
+            "PF" => OsuMods::PERFECT,
+            "FD" => OsuMods::FADEIN,
+            "V2" => OsuMods::SCOREV2,
+            "MR" => OsuMods::MIRROR,
+            _ => return None,
+        };
+
+        Some(mods)
+    }
+
+    /// Same as `from_str` but fails on unknown acronyms instead of skipping them,
+    /// meant for user input
+    pub fn from_str_strict(s: &str) -> Result<Self, OsuApiError> {
+        let s = s.trim().to_uppercase();
+        let mut flags = OsuMods::empty();
+        let mut unknown = Vec::new();
+
+        for acronym in cut(&s, 2, true)? {
+            match OsuMods::from_acronym(acronym) {
+                Some(mods) => flags |= mods,
+                None => unknown.push(acronym.to_owned()),
+            }
         }
-        if self.contains(OsuMods::MIRROR) {
-            res.push_str("MR")
+
+        if !unknown.is_empty() {
+            return Err(OsuApiError::UnknownMods(unknown));
         }
 
-        res
+        Ok(flags)
     }
 }
 
+/// Lenient parsing, unknown acronyms are skipped
 impl FromStr for OsuMods {
     type Err = OsuApiError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = s.to_uppercase();
-        let mut flags = OsuMods::empty();
 
-        for abbrev in cut(&s, 2) {
-            flags = match abbrev {
-                "NM" => flags | OsuMods::NOMOD,
-                "NF" => flags | OsuMods::NOFAIL,
-                "EZ" => flags | OsuMods::EASY,
-                "TD" => flags | OsuMods::TOUCHDEVICE,
-                "HD" => flags | OsuMods::HIDDEN,
-                "HR" => flags | OsuMods::HARDROCK,
-                "SD" => flags | OsuMods::SUDDENDEATH,
-                "DT" => flags | OsuMods::DOUBLETIME,
-                "RX" => flags | OsuMods::RELAX,
-                "HT" => flags | OsuMods::HALFTIME,
-                "NC" => flags | OsuMods::NIGHTCORE,
-                "FL" => flags | OsuMods::FLASHLIGHT,
-                "SO" => flags | OsuMods::SPUNOUT,
-                "PF" => flags | OsuMods::PERFECT,
-                "FD" => flags | OsuMods::FADEIN,
-                _ => flags,
-            };
-        }
-
-        Ok(flags)
+        Ok(cut(&s, 2, false)?
+            .into_iter()
+            .filter_map(OsuMods::from_acronym)
+            .fold(OsuMods::empty(), |flags, mods| flags | mods))
     }
 }
 
@@ -197,30 +282,18 @@ impl<'de> Visitor<'de> for OsuModsVisitor {
         Ok(mods)
     }
 
+    fn visit_u64<E: Error>(self, v: u64) -> Result<Self::Value, E> {
+        let bits = u32::try_from(v)
+            .map_err(|_| Error::invalid_value(Unexpected::Unsigned(v), &"mods bitflags"))?;
+
+        OsuMods::try_from(bits)
+            .map_err(|_| Error::invalid_value(Unexpected::Unsigned(v), &"known mods bitflags"))
+    }
+
     fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
-        let mods = match v {
-            "NM" => OsuMods::NOMOD,
-            "NF" => OsuMods::NOFAIL,
-            "EZ" => OsuMods::EASY,
-            "TD" => OsuMods::TOUCHDEVICE,
-            "HD" => OsuMods::HIDDEN,
-            "HR" => OsuMods::HARDROCK,
-            "SD" => OsuMods::SUDDENDEATH,
-            "DT" => OsuMods::DOUBLETIME,
-            "RX" => OsuMods::RELAX,
-            "HT" => OsuMods::HALFTIME,
-            "NC" => OsuMods::NIGHTCORE,
-            "FL" => OsuMods::FLASHLIGHT,
-            "SO" => OsuMods::SPUNOUT,
-            "PF" => OsuMods::PERFECT,
-            "FD" => OsuMods::FADEIN,
-            _ => {
-                return Err(Error::invalid_value(
-                    Unexpected::Str(v),
-                    &r#"valid mods acronym"#,
-                ))
-            }
-        };
+        let mods = OsuMods::from_acronym(v).ok_or_else(|| {
+            Error::invalid_value(Unexpected::Str(v), &r#"valid mods acronym"#)
+        })?;
 
         Ok(mods)
     }
@@ -235,11 +308,22 @@ impl<'de> Deserialize<'de> for OsuMods {
 
 #[derive(Debug, Deserialize)]
 pub struct BeatmapCompact {
+    pub id: i64,
     pub version: String,
+    #[serde(default)]
+    pub mode: GameMode,
+    #[serde(default)]
+    pub max_combo: Option<u32>,
+    /// Full map length in seconds (drain time is `hit_length`)
+    #[serde(default)]
+    pub total_length: u32,
+    #[serde(default)]
+    pub bpm: f32,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct BeatmapSetCompact {
+    pub id: i64,
     pub artist: String,
     pub artist_unicode: String,
     pub creator: String,
@@ -260,27 +344,195 @@ pub struct Score {
     #[serde(deserialize_with = "deserialize_utc_datetime")]
     pub created_at: DateTime<Utc>,
     pub replay: bool,
+    /// `false` only for failed recent scores
+    #[serde(default = "default_passed")]
+    pub passed: bool,
+    /// Full combo as reported by the api
+    #[serde(default)]
+    pub perfect: bool,
+    #[serde(default)]
+    pub mode: GameMode,
+    #[serde(default)]
+    pub max_combo: u32,
+    #[serde(default)]
+    pub statistics: ScoreStatistics,
     pub beatmapset: BeatmapSetCompact,
     pub beatmap: BeatmapCompact,
 }
 
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ScoreStatistics {
+    pub count_300: u32,
+    pub count_100: u32,
+    pub count_50: u32,
+    pub count_miss: u32,
+    /// MAX judgements in mania (gekis in other modes)
+    pub count_geki: u32,
+    /// 200 judgements in mania (katus in other modes)
+    pub count_katu: u32,
+}
+
+impl Score {
+    /// No misses and (if map's max combo is known) full combo, so
+    /// sliderbreaks are caught only when beatmap carries `max_combo`
+    pub fn is_fc(&self) -> bool {
+        self.statistics.count_miss == 0
+            && self.beatmap.max_combo.is_none_or(|max| self.max_combo >= max)
+    }
+
+    /// ScoreV2 scores have different score scale and aren't comparable with classic ones
+    pub fn is_classic_scoring(&self) -> bool {
+        !self.mods.contains(OsuMods::SCOREV2)
+    }
+
+    /// Map length in seconds with DT/HT speed taken into account
+    pub fn effective_length(&self) -> f32 {
+        self.beatmap.total_length as f32 / self.mods.clock_rate()
+    }
+
+    /// `None` for unranked scores and maps without known length
+    pub fn pp_per_minute(&self) -> Option<f32> {
+        let minutes = self.effective_length() / 60.0;
+
+        match minutes > 0.0 {
+            true => self.pp.map(|pp| pp / minutes),
+            false => None,
+        }
+    }
+
+    /// Profile of the player who set the score
+    pub fn profile_link(&self) -> String {
+        format!("https://osu.ppy.sh/users/{}", self.user_id)
+    }
+
+    pub fn score_link(&self) -> String {
+        format!("https://osu.ppy.sh/scores/{}", self.id)
+    }
+
+    /// Link to the exact difficulty the score was set on
+    pub fn beatmap_link(&self) -> String {
+        format!(
+            "https://osu.ppy.sh/beatmapsets/{}#{}/{}",
+            self.beatmapset.id, self.beatmap.mode, self.beatmap.id
+        )
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct UserCompact {
     pub id: i64,
     pub username: String,
+    #[serde(default)]
+    pub country_code: String,
+    #[serde(default)]
+    pub country: Option<Country>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Country {
+    pub code: String,
+    pub name: String,
 }
 
+/// Ranking endpoints return only part of statistics, so everything
+/// besides the user is optional
 #[derive(Debug, Clone, Deserialize)]
 pub struct UserStatistics {
+    #[serde(default, deserialize_with = "null_as_zero")]
     pub pp: f32,
-    pub global_rank: i32,
+    /// `None` for inactive players that lost their rank
+    #[serde(default)]
+    pub global_rank: Option<i32>,
+    /// Missing from some endpoints
+    #[serde(default)]
+    pub country_rank: Option<i32>,
     pub user: UserCompact,
 }
 
+/// Response of the `/users/{user}/{mode}` endpoint
+#[derive(Debug, Deserialize)]
+pub struct UserExtended {
+    pub id: i64,
+    pub username: String,
+    #[serde(default)]
+    pub country_code: String,
+    #[serde(default)]
+    pub country: Option<Country>,
+    pub statistics: UserExtendedStatistics,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserExtendedStatistics {
+    #[serde(default, deserialize_with = "null_as_zero")]
+    pub pp: f32,
+    #[serde(default)]
+    pub global_rank: Option<i32>,
+    #[serde(default)]
+    pub country_rank: Option<i32>,
+}
+
+impl From<UserExtended> for UserStatistics {
+    fn from(user: UserExtended) -> Self {
+        Self {
+            pp: user.statistics.pp,
+            global_rank: user.statistics.global_rank,
+            country_rank: user.statistics.country_rank,
+            user: UserCompact {
+                id: user.id,
+                username: user.username,
+                country_code: user.country_code,
+                country: user.country,
+            },
+        }
+    }
+}
+
+/// Way to look up single user
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserLookup {
+    Id(i64),
+    Username(String),
+}
+
+impl FromStr for UserLookup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(id) = s.parse() {
+            return Ok(UserLookup::Id(id));
+        }
+
+        let valid_chars = s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || " -_[]".contains(c));
+
+        if !valid_chars || s.len() < 2 || s.len() > 15 {
+            return Err(format!("{s} is not a valid user id or username"));
+        }
+
+        Ok(UserLookup::Username(s.to_owned()))
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RankingResponse {
     pub ranking: Vec<UserStatistics>,
-    pub total: i32,
+    // Missing for spotlight (charts) rankings
+    #[serde(default)]
+    pub total: Option<i32>,
+    #[serde(default)]
+    pub spotlight: Option<SpotlightInfo>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpotlightInfo {
+    pub id: u32,
+    pub name: String,
+    pub start_date: String,
+    pub end_date: String,
+    #[serde(default)]
+    pub participant_count: Option<i32>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -295,203 +547,1794 @@ pub struct OsuApi {
     client_id: i32,
     client_secret: String,
     token: Option<String>,
+    base_url: String,
+    user_agent: String,
+    timeout: Duration,
+    retry_policy: RetryPolicy,
+    limiter: Semaphore,
+    pacer: Option<RequestPacer>,
+    metrics: Arc<Mutex<RequestMetrics>>,
+    cache: Option<ResponseCache>,
+    max_requests: Option<usize>,
+    sent: AtomicUsize,
+    stopped: AtomicBool,
 }
 
-pub enum RankingType {
-    Country { code: String }, // Replace with cow
-    Global,
+/// Spaces requests evenly so that no more than `rpm` are sent per minute
+#[derive(Debug)]
+struct RequestPacer {
+    interval: Duration,
+    next: Mutex<Instant>,
 }
 
-impl OsuApi {
-    pub async fn new(client_id: i32, client_secret: &str) -> ApiResult<Self> {
-        let https = HttpsConnectorBuilder::new()
-            .with_native_roots()
-            .https_only()
-            .enable_http1()
-            .build();
-
-        let client = Client::builder().build(https);
-
-        let mut api = Self {
-            client,
-            client_id,
-            client_secret: client_secret.to_string(),
-            token: None,
-        };
+impl RequestPacer {
+    fn new(rpm: NonZeroU32) -> Self {
+        Self {
+            interval: Duration::from_secs(60) / rpm.get(),
+            next: Mutex::new(Instant::now()),
+        }
+    }
 
-        api.token = Some(api.request_oauth().await?);
+    /// Takes next free slot, returns the moment request can be sent at
+    fn reserve(&self) -> Instant {
+        let mut next = self.next.lock().unwrap();
+        let at = (*next).max(Instant::now());
+        *next = at + self.interval;
 
-        Ok(api)
+        at
     }
 
-    pub async fn get_user_best_scores(&self, user_id: i64) -> ApiResult<Vec<Score>> {
-        let mut link = format!(
-            "https://osu.ppy.sh/api/v2/users/{}/scores/{}",
-            user_id, "best"
-        );
-        let _ = write!(link, "?mode=osu");
-        let _ = write!(link, "&limit=100");
-
-        self.make_request(Method::GET, &link).await
+    async fn wait(&self) {
+        tokio::time::sleep_until(self.reserve()).await;
     }
+}
 
-    pub async fn get_ranking(
-        &self, 
-        ranking: RankingType,
-        pages: i32
-    ) -> ApiResult<Vec<UserStatistics>> {
+/// How failed requests are retried, delay doubles after each attempt
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    /// Longest `Retry-After` that is waited out, request fails
+    /// with [`OsuApiError::RateLimited`] if server asks for more
+    pub max_retry_after: Duration,
+}
 
-        let mut buff = Vec::with_capacity(pages as usize * 50);
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: RETRY_BASE_DELAY,
+            max_retry_after: DEFAULT_MAX_RETRY_AFTER,
+        }
+    }
+}
 
-        for page in 1..=pages {
-            let mut link = format!(
-                "https://osu.ppy.sh/api/v2/rankings/{}/{}",
-                "osu", "performance"
-            );
+impl RetryPolicy {
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay.saturating_mul(2u32.saturating_pow(attempt))
+    }
 
-            match &ranking {
-                RankingType::Country { code } => {
-                    let _ = write!(
-                        link, 
-                        "?country={code}&cursor[page]={page}"
-                    );
-                },
-                RankingType::Global => {
-                    let _ = write!(
-                        link,
-                        "?cursor[page]={page}"
-                    );
-                }
+    /// Delay before retrying after `err`, `None` if server asks to wait
+    /// longer than `max_retry_after`
+    fn retry_delay(&self, err: &OsuApiError, attempt: u32) -> Option<Duration> {
+        match err {
+            OsuApiError::RateLimited { retry_after: Some(after), .. } => {
+                (*after <= self.max_retry_after).then_some(*after)
             }
+            _ => Some(self.delay(attempt)),
+        }
+    }
+}
 
-            let r: RankingResponse = self.make_request(Method::GET, &link).await?;
+/// Configures optional [`OsuApi`] settings before performing oauth handshake
+#[derive(Debug, Clone)]
+pub struct OsuApiBuilder {
+    client_id: Option<i32>,
+    client_secret: Option<String>,
+    base_url: String,
+    user_agent: String,
+    timeout: Duration,
+    retry_policy: RetryPolicy,
+    concurrency: usize,
+    rpm: Option<NonZeroU32>,
+    cache: Option<ResponseCache>,
+    max_requests: Option<usize>,
+    max_idle_per_host: Option<usize>,
+    keep_alive: Option<Duration>,
+    pool_idle_timeout: Option<Duration>,
+}
 
-            buff.extend(r.ranking);
+impl Default for OsuApiBuilder {
+    fn default() -> Self {
+        Self {
+            client_id: None,
+            client_secret: None,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            timeout: DEFAULT_TIMEOUT,
+            retry_policy: RetryPolicy::default(),
+            concurrency: Semaphore::MAX_PERMITS,
+            rpm: None,
+            cache: None,
+            max_requests: None,
+            max_idle_per_host: None,
+            keep_alive: None,
+            pool_idle_timeout: None,
         }
+    }
+}
 
-        Ok(buff)
+impl OsuApiBuilder {
+    pub fn client_id(mut self, client_id: i32) -> Self {
+        self.client_id = Some(client_id);
+        self
     }
 
-    // Make request with corresponding token (that we requested earlier
-    async fn make_request<T: DeserializeOwned>(&self, method: Method, link: &str) -> ApiResult<T> {
-        let token = match &self.token {
-            Some(t) => t.as_str(),
-            None => return Err(OsuApiError::NoToken),
-        };
+    pub fn client_secret(mut self, client_secret: &str) -> Self {
+        self.client_secret = Some(client_secret.to_string());
+        self
+    }
 
-        let req = Request::builder()
-            .method(method)
-            .uri(link)
-            .header(AUTHORIZATION, format!("Bearer {token}"))
-            .header(ACCEPT, "application/json")
-            .header(CONTENT_TYPE, "application/json")
-            .header(USER_AGENT, "vasteri-bebrik")
-            .body(Body::empty())?;
+    /// Timeout for a single http request
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
 
-        let mut resp = self.client.request(req).await?;
-        let bytes = self.handle_error(&mut resp).await?;
-        
-        self.parse_bytes(&bytes).await
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = user_agent.into();
+        self
     }
 
-    async fn handle_error(&self, res: &mut hyper::Response<Body>) -> ApiResult<Bytes> {
-        let bytes = hyper::body::to_bytes(res.body_mut()).await?;
-        match res.status() {
-            StatusCode::OK => return Ok(bytes),
-            StatusCode::BAD_REQUEST => return Err(OsuApiError::BadRequest),
-            StatusCode::TOO_MANY_REQUESTS => return Err(OsuApiError::RateLimited),
-            StatusCode::SERVICE_UNAVAILABLE => return Err(OsuApiError::ServiceUnavailable),
-            _ => (),
-        };
+    /// Base url without trailing slash e.g. https://osu.ppy.sh
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
 
-        let err: ApiErrorResponse =
-            serde_json::from_slice(&bytes).map_err(|e| OsuApiError::ParsingError {
-                inner: e,
-                body: bytes.clone(),
-            })?;
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
 
-        Err(OsuApiError::ApiError { inner: err })
+    /// Shorthand for overriding only `max_retries` of the retry policy
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.retry_policy.max_retries = max_retries;
+        self
     }
 
-    async fn parse_bytes<T: DeserializeOwned>(&self, bytes: &Bytes) -> ApiResult<T> {
-        serde_json::from_slice(bytes).map_err(|e| OsuApiError::ParsingError {
-            inner: e,
-            body: bytes.clone(),
-        })
+    /// Shorthand for overriding only `max_retry_after` of the retry policy
+    pub fn max_retry_after(mut self, max_retry_after: Duration) -> Self {
+        self.retry_policy.max_retry_after = max_retry_after;
+        self
     }
 
-    async fn request_oauth(&self) -> ApiResult<String> {
-        let data = format!(
-            r#"{{
-            "client_id":"{}",
-            "client_secret":"{}",
-            "grant_type":"client_credentials",
-            "scope":"public" 
-        }}"#,
-            &self.client_id, &self.client_secret
-        );
+    /// Maximum amount of requests in flight at the same time
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.clamp(1, Semaphore::MAX_PERMITS);
+        self
+    }
 
-        let req = Request::builder()
-            .method(Method::POST)
-            .header("Accept", "application/json")
-            .header("Content-Type", "application/json")
-            .uri("https://osu.ppy.sh/oauth/token")
-            .body(Body::from(data))?;
+    /// Maximum amount of requests per minute, unlimited by default
+    pub fn rpm(mut self, rpm: NonZeroU32) -> Self {
+        self.rpm = Some(rpm);
+        self
+    }
 
-        let mut response = self.client.request(req).await?;
+    /// Cache for user scores and leaderboard pages responses
+    pub fn cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
 
-        let bytes = self.handle_error(&mut response).await?;
+    /// Requests beyond this amount fail with `BudgetExhausted`
+    pub fn max_requests(mut self, max_requests: usize) -> Self {
+        self.max_requests = Some(max_requests);
+        self
+    }
 
-        let r: OauthResponse = self.parse_bytes(&bytes).await?;
+    /// Maximum amount of idle connections kept in the pool, unlimited by default
+    pub fn max_idle_per_host(mut self, max_idle_per_host: usize) -> Self {
+        self.max_idle_per_host = Some(max_idle_per_host);
+        self
+    }
 
-        Ok(r.access_token)
+    /// Interval of tcp keepalive probes, disabled by default
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::osu_api::{OsuApi, RankingType};
-    use std::env;
-    use eyre::Result;
-    use dotenv::dotenv;
+    /// How long idle connection is kept in the pool, 90 seconds by default
+    pub fn pool_idle_timeout(mut self, pool_idle_timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(pool_idle_timeout);
+        self
+    }
 
-    #[tokio::test]
-    async fn test_limit() -> Result<()> {
-        dotenv()?;
+    pub async fn build(self) -> ApiResult<OsuApi> {
+        let (Some(client_id), Some(client_secret)) = (self.client_id, self.client_secret) else {
+            return Err(OsuApiError::MissingCredentials);
+        };
 
-        let api = OsuApi::new(
-            env::var("CLIENT_ID")?.parse()?,
-            env::var("CLIENT_SECRET")?.as_str(),
-        )
-        .await?;
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_keepalive(self.keep_alive);
 
-        let ranking = RankingType::Country{ code: "by".to_owned() };
+        let https = HttpsConnectorBuilder::new().with_native_roots();
 
-        let lb = api.get_ranking(ranking, 2).await?;
+        // Plain http is only allowed when it's asked for explicitly e.g. for local mock server
+        let https = match self.base_url.starts_with("http://") {
+            true => https.https_or_http(),
+            false => https.https_only(),
+        };
 
-        assert_eq!(lb.len(), 100);
+        let https = https
+            .enable_http1()
+            .enable_http2()
+            .wrap_connector(http);
 
-        Ok(())
-    }
+        // Protocol is negotiated with ALPN, http/1.1 is used when server doesn't offer h2
+        let mut client = Client::builder();
+        client.http2_only(false);
 
-    #[tokio::test]
-    async fn test_get_user_best_scores_health() -> Result<()> {
-        dotenv()?;
+        if let Some(max_idle) = self.max_idle_per_host {
+            client.pool_max_idle_per_host(max_idle);
+        }
 
-        let api = OsuApi::new(
-            env::var("CLIENT_ID")?.parse()?,
+        if let Some(idle_timeout) = self.pool_idle_timeout {
+            client.pool_idle_timeout(idle_timeout);
+        }
+
+        let client = client.build(https);
+
+        let mut api = OsuApi {
+            client,
+            client_id,
+            client_secret,
+            token: None,
+            base_url: self.base_url,
+            user_agent: self.user_agent,
+            timeout: self.timeout,
+            retry_policy: self.retry_policy,
+            limiter: Semaphore::new(self.concurrency),
+            pacer: self.rpm.map(RequestPacer::new),
+            metrics: Arc::default(),
+            cache: self.cache,
+            max_requests: self.max_requests,
+            sent: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+        };
+
+        api.token = Some(api.request_oauth().await?);
+
+        Ok(api)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GameMode {
+    #[default]
+    Osu,
+    Taiko,
+    Fruits,
+    Mania,
+}
+
+impl fmt::Display for GameMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameMode::Osu => f.write_str("osu"),
+            GameMode::Taiko => f.write_str("taiko"),
+            GameMode::Fruits => f.write_str("fruits"),
+            GameMode::Mania => f.write_str("mania"),
+        }
+    }
+}
+
+impl FromStr for GameMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "osu" => Ok(GameMode::Osu),
+            "taiko" => Ok(GameMode::Taiko),
+            "fruits" | "catch" => Ok(GameMode::Fruits),
+            "mania" => Ok(GameMode::Mania),
+            _ => Err(format!("unknown mode {s}, expected osu, taiko, fruits or mania")),
+        }
+    }
+}
+
+/// Collects pages of `page_size` items requested by offset, stops after
+/// an empty or partial page or once `max_pages` are fetched
+async fn paginate<T, F, Fut>(page_size: u32, max_pages: Option<u32>, mut fetch: F) -> ApiResult<Vec<T>>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = ApiResult<Vec<T>>>,
+{
+    let mut items = Vec::new();
+    let mut page = 0;
+
+    while max_pages.is_none_or(|max| page < max) {
+        let chunk = fetch(page * page_size).await?;
+        let len = chunk.len();
+
+        items.extend(chunk);
+        page += 1;
+
+        if len < page_size as usize {
+            break;
+        }
+    }
+
+    Ok(items)
+}
+
+/// Leaderboard page link, spotlights are always ranked by their own score
+fn ranking_link(
+    base_url: &str,
+    mode: GameMode,
+    ranking: &RankingType,
+    metric: RankingMetric,
+    page: i32,
+) -> String {
+    let kind = match ranking {
+        RankingType::Spotlight { .. } => "charts".to_owned(),
+        _ => metric.to_string(),
+    };
+
+    let mut link = format!(
+        "{}/api/v2/rankings/{}/{}",
+        base_url, mode, kind
+    );
+
+    match ranking {
+        RankingType::Country { code } => {
+            let _ = write!(
+                link, 
+                "?country={code}&cursor[page]={page}"
+            );
+        },
+        RankingType::Global => {
+            let _ = write!(
+                link,
+                "?cursor[page]={page}"
+            );
+        },
+        RankingType::Spotlight { id } => {
+            let _ = write!(link, "?spotlight={id}");
+        }
+        RankingType::Countries { .. } => {
+            unreachable!("multiple countries are fetched one leaderboard at a time")
+        }
+    }
+
+    link
+}
+
+/// Amount of leaderboard pages required to cover `users` top users
+fn ranking_pages(users: usize) -> i32 {
+    users.div_ceil(RANKING_PAGE_SIZE) as i32
+}
+
+/// Pages to fetch for `limit` top users of leaderboard with `total` players
+fn required_pages(limit: usize, total: i32) -> i32 {
+    ranking_pages(limit.min(total.max(0) as usize))
+}
+
+/// Merges several leaderboards deduplicating players by id (the entry with
+/// higher pp wins), result is sorted by pp descending
+pub fn merge_rankings(rankings: Vec<Ranking>) -> Ranking {
+    let mut merged: BTreeMap<i64, UserStatistics> = BTreeMap::new();
+    let pages = rankings.iter().map(|r| r.pages).sum();
+
+    for stats in rankings.into_iter().flat_map(|r| r.users) {
+        match merged.entry(stats.user.id) {
+            Entry::Occupied(mut e) if e.get().pp < stats.pp => {
+                e.insert(stats);
+            }
+            Entry::Occupied(_) => {}
+            Entry::Vacant(e) => {
+                e.insert(stats);
+            }
+        }
+    }
+
+    let mut users: Vec<UserStatistics> = merged.into_values().collect();
+    users.sort_by(|a, b| b.pp.total_cmp(&a.pp));
+
+    Ranking {
+        total: users.len() as i32,
+        users,
+        spotlight: None,
+        pages,
+    }
+}
+
+/// Kind of user scores list
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScoreType {
+    Best,
+    Recent { include_fails: bool },
+    Firsts,
+}
+
+impl fmt::Display for ScoreType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScoreType::Best => f.write_str("best"),
+            ScoreType::Recent { .. } => f.write_str("recent"),
+            ScoreType::Firsts => f.write_str("firsts"),
+        }
+    }
+}
+
+/// Failed recent scores are excluded, `include_fails` is set separately
+impl FromStr for ScoreType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "best" => Ok(ScoreType::Best),
+            "recent" => Ok(ScoreType::Recent { include_fails: false }),
+            "firsts" => Ok(ScoreType::Firsts),
+            _ => Err(format!("unknown score type {s}, expected best, recent or firsts")),
+        }
+    }
+}
+
+/// Fetched leaderboard along with the total amount of ranked players in it
+#[derive(Debug)]
+pub struct Ranking {
+    pub users: Vec<UserStatistics>,
+    pub total: i32,
+    pub spotlight: Option<SpotlightInfo>,
+    /// Leaderboard pages requested to get `users`
+    pub pages: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RankingType {
+    Country { code: String }, // Replace with cow
+    /// Several country leaderboards merged into one
+    Countries { codes: Vec<String> },
+    Global,
+    Spotlight { id: u32 },
+}
+
+impl fmt::Display for RankingType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RankingType::Country { code } => write!(f, "Country {code}"),
+            RankingType::Countries { codes } => write!(f, "Countries {}", codes.join(", ")),
+            RankingType::Global => f.write_str("Global leaderboard"),
+            RankingType::Spotlight { id } => write!(f, "Spotlight {id}"),
+        }
+    }
+}
+
+/// What players are ranked by, ranked score leaderboard exists
+/// only globally (there's no country filter for it)
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum RankingMetric {
+    #[default]
+    Performance,
+    Score,
+}
+
+impl fmt::Display for RankingMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RankingMetric::Performance => f.write_str("performance"),
+            RankingMetric::Score => f.write_str("score"),
+        }
+    }
+}
+
+impl FromStr for RankingMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pp" | "performance" => Ok(RankingMetric::Performance),
+            "score" => Ok(RankingMetric::Score),
+            _ => Err(format!("unknown metric {s}, expected pp or score")),
+        }
+    }
+}
+
+impl RankingType {
+    /// Name of leaderboard page in the response cache
+    fn cache_key(&self, mode: GameMode, metric: RankingMetric, page: i32) -> String {
+        let kind = match self {
+            RankingType::Country { code } => format!("country-{code}"),
+            RankingType::Countries { codes } => format!("countries-{}", codes.join("-")),
+            RankingType::Global => "global".to_owned(),
+            RankingType::Spotlight { id } => format!("spotlight-{id}"),
+        };
+
+        match metric {
+            RankingMetric::Performance => format!("ranking-{mode}-{kind}-{page}"),
+            RankingMetric::Score => format!("ranking-{mode}-{kind}-score-{page}"),
+        }
+    }
+}
+
+impl OsuApi {
+    pub async fn new(client_id: i32, client_secret: &str) -> ApiResult<Self> {
+        Self::builder()
+            .client_id(client_id)
+            .client_secret(client_secret)
+            .build()
+            .await
+    }
+
+    pub fn builder() -> OsuApiBuilder {
+        OsuApiBuilder::default()
+    }
+
+    pub async fn get_user_scores(
+        &self,
+        user_id: i64,
+        mode: GameMode,
+        score_type: ScoreType,
+        limit: u32,
+    ) -> ApiResult<Vec<Score>> {
+        self.get_user_scores_page(user_id, mode, score_type, limit, 0).await
+    }
+
+    /// Every score of the list, pages are fetched until an empty (or partial)
+    /// one. Best scores are capped at a single page by the api itself
+    pub async fn get_user_scores_all(
+        &self,
+        user_id: i64,
+        mode: GameMode,
+        score_type: ScoreType,
+        max_pages: Option<u32>,
+    ) -> ApiResult<Vec<Score>> {
+        let max_pages = match score_type {
+            ScoreType::Best => Some(1),
+            _ => max_pages,
+        };
+
+        paginate(USER_SCORES_PAGE_SIZE, max_pages, |offset| {
+            self.get_user_scores_page(user_id, mode, score_type, USER_SCORES_PAGE_SIZE, offset)
+        })
+        .await
+    }
+
+    async fn get_user_scores_page(
+        &self,
+        user_id: i64,
+        mode: GameMode,
+        score_type: ScoreType,
+        limit: u32,
+        offset: u32,
+    ) -> ApiResult<Vec<Score>> {
+        let mut link = format!(
+            "{}/api/v2/users/{}/scores/{}",
+            self.base_url, user_id, score_type
+        );
+        let _ = write!(link, "?mode={mode}");
+        let _ = write!(link, "&limit={limit}");
+
+        if offset > 0 {
+            let _ = write!(link, "&offset={offset}");
+        }
+
+        if let ScoreType::Recent { include_fails: true } = score_type {
+            let _ = write!(link, "&include_fails=1");
+        }
+
+        let key = format!("scores-{user_id}-{mode}-{score_type}-{limit}-{offset}");
+
+        self.make_cached_request(&key, &link).await
+    }
+
+    pub async fn get_score_by_id(&self, mode: GameMode, score_id: i64) -> ApiResult<Score> {
+        let link = format!(
+            "{}/api/v2/scores/{}/{}",
+            self.base_url, mode, score_id
+        );
+
+        self.make_request(Method::GET, &link).await
+    }
+
+    /// Checks whether replay can actually be downloaded, body of the
+    /// replay itself is never read. Tokens that aren't allowed to download
+    /// replays (client credentials ones) get 403 which is treated as unavailable
+    pub async fn is_replay_available(&self, mode: GameMode, score_id: i64) -> ApiResult<bool> {
+        let link = format!(
+            "{}/api/v2/scores/{}/{}/download",
+            self.base_url, mode, score_id
+        );
+
+        self.with_retries(|| async {
+            let req = self.authorized_request(Method::GET, &link)?;
+            let mut resp = self.send(req).await?;
+
+            Self::replay_status(&mut resp).await
+        })
+        .await
+    }
+
+    async fn replay_status(res: &mut hyper::Response<Body>) -> ApiResult<bool> {
+        match res.status() {
+            StatusCode::OK => Ok(true),
+            StatusCode::NOT_FOUND | StatusCode::FORBIDDEN => Ok(false),
+            _ => Self::handle_error(res).await.map(|_| false),
+        }
+    }
+
+    pub async fn get_user(&self, mode: GameMode, user: &UserLookup) -> ApiResult<UserExtended> {
+        let link = match user {
+            UserLookup::Id(id) => format!(
+                "{}/api/v2/users/{}/{}?key=id",
+                self.base_url, id, mode
+            ),
+            UserLookup::Username(name) => format!(
+                "{}/api/v2/users/{}/{}?key=username",
+                self.base_url,
+                name.replace(' ', "%20").replace('[', "%5B").replace(']', "%5D"),
+                mode
+            ),
+        };
+
+        self.make_request(Method::GET, &link).await
+    }
+
+    /// Cheap authorized request (first page of osu! leaderboard) to make sure
+    /// credentials are good before a long run
+    pub async fn validate(&self) -> ApiResult<()> {
+        let link = ranking_link(
+            &self.base_url,
+            GameMode::Osu,
+            &RankingType::Global,
+            RankingMetric::Performance,
+            1,
+        );
+        let _: RankingResponse = self.make_request(Method::GET, &link).await?;
+
+        Ok(())
+    }
+
+    /// Fetches up to `limit` top users of the leaderboard, pages
+    /// are requested only as long as there are users left
+    pub async fn get_ranking(
+        &self, 
+        mode: GameMode,
+        ranking: RankingType,
+        metric: RankingMetric,
+        limit: usize
+    ) -> ApiResult<Ranking> {
+        let ranking = match ranking {
+            RankingType::Countries { codes } => {
+                self.get_country_rankings(mode, &codes, metric, limit).await?
+            }
+            ranking => self.get_single_ranking(mode, ranking, metric, limit).await?,
+        };
+
+        if ranking.users.is_empty() {
+            return Err(OsuApiError::EmptyRanking);
+        }
+
+        Ok(ranking)
+    }
+
+    /// Fetches every country leaderboard in parallel and merges them
+    async fn get_country_rankings(
+        &self,
+        mode: GameMode,
+        codes: &[String],
+        metric: RankingMetric,
+        limit: usize,
+    ) -> ApiResult<Ranking> {
+        let rankings = try_join_all(codes.iter().map(|code| {
+            self.get_single_ranking(mode, RankingType::Country { code: code.clone() }, metric, limit)
+        }))
+        .await?;
+
+        let mut merged = merge_rankings(rankings);
+        merged.users.truncate(limit);
+
+        Ok(merged)
+    }
+
+    async fn get_single_ranking(
+        &self,
+        mode: GameMode,
+        ranking: RankingType,
+        metric: RankingMetric,
+        limit: usize
+    ) -> ApiResult<Ranking> {
+        // First page tells how many players there are in total
+        let first = self.get_ranking_page(mode, &ranking, metric, 1).await?;
+
+        // Spotlight rankings aren't paginated and come without total
+        let total = first.total.unwrap_or(first.ranking.len() as i32);
+
+        if limit != WHOLE_RANKING && (total.max(0) as usize) < limit {
+            warn!("{ranking} has only {total} ranked players, {limit} were requested");
+        }
+
+        let pages = match ranking {
+            RankingType::Spotlight { .. } => 1,
+            _ => required_pages(limit, total),
+        };
+        let spotlight = first.spotlight;
+
+        let mut buff = first.ranking;
+
+        let rest = fetch_ordered(2..=pages, RANKING_PAGES_CONCURRENCY, |page| {
+            let ranking = &ranking;
+            async move { Ok(self.get_ranking_page(mode, ranking, metric, page).await?.ranking) }
+        })
+        .await?;
+
+        buff.extend(rest.into_iter().flatten());
+        buff.truncate(limit);
+
+        Ok(Ranking { users: buff, total, spotlight, pages: pages.max(1) })
+    }
+
+    /// Leaderboard pages go through the response cache (if there's one) since
+    /// the same pages are requested again on every run
+    async fn get_ranking_page(
+        &self,
+        mode: GameMode,
+        ranking: &RankingType,
+        metric: RankingMetric,
+        page: i32,
+    ) -> ApiResult<RankingResponse> {
+        let link = ranking_link(&self.base_url, mode, ranking, metric, page);
+
+        self.make_cached_request(&ranking.cache_key(mode, metric, page), &link).await
+    }
+
+    // Make request with corresponding token, retrying on transient errors
+    async fn make_request<T: DeserializeOwned>(&self, method: Method, link: &str) -> ApiResult<T> {
+        let bytes = self.request_bytes(method, link).await?;
+
+        self.parse_bytes(&bytes).await
+    }
+
+    /// Same as `make_request` but goes through the response cache (if there's one)
+    async fn make_cached_request<T: DeserializeOwned>(&self, key: &str, link: &str) -> ApiResult<T> {
+        let bytes = match &self.cache {
+            Some(cache) => {
+                cache
+                    .get_or_fetch(key, || self.request_bytes(Method::GET, link))
+                    .await?
+            }
+            None => self.request_bytes(Method::GET, link).await?,
+        };
+
+        self.parse_bytes(&bytes).await
+    }
+
+    async fn request_bytes(&self, method: Method, link: &str) -> ApiResult<Bytes> {
+        self.with_retries(|| self.try_request(method.clone(), link)).await
+    }
+
+    /// Runs `request` until it succeeds, fails with non-retryable
+    /// error or retries allowed by the retry policy run out
+    async fn with_retries<T, F, Fut>(&self, mut request: F) -> ApiResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ApiResult<T>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            let res = request().await;
+
+            let delay = match &res {
+                Err(e) if e.is_retryable() && attempt < self.retry_policy.max_retries => {
+                    self.retry_policy.retry_delay(e, attempt)
+                }
+                _ => None,
+            };
+
+            match delay {
+                Some(delay) => {
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => {
+                    if attempt > 0 {
+                        self.metrics.lock().unwrap().record_retried(res.is_ok());
+                    }
+
+                    return res;
+                }
+            }
+        }
+    }
+
+    // Make request with corresponding token (that we requested earlier
+    async fn try_request(&self, method: Method, link: &str) -> ApiResult<Bytes> {
+        let req = self.authorized_request(method, link)?;
+
+        let mut resp = self.send(req).await?;
+
+        Self::handle_error(&mut resp).await
+    }
+
+    fn authorized_request(&self, method: Method, link: &str) -> ApiResult<Request<Body>> {
+        let token = match &self.token {
+            Some(t) => t.as_str(),
+            None => return Err(OsuApiError::NoToken),
+        };
+
+        let req = Request::builder()
+            .method(method)
+            .uri(link)
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .header(ACCEPT, "application/json")
+            .header(ACCEPT_ENCODING, "gzip, deflate")
+            .header(CONTENT_TYPE, "application/json")
+            .header(USER_AGENT, self.user_agent.as_str())
+            .body(Body::empty())?;
+
+        Ok(req)
+    }
+
+    async fn send(&self, req: Request<Body>) -> ApiResult<Response<Body>> {
+        if let Some(pacer) = &self.pacer {
+            pacer.wait().await;
+        }
+
+        // Semaphore is never closed
+        let _permit = self.limiter.acquire().await.unwrap();
+
+        // Checked once permit is taken since requests could be waiting for it long
+        let sent = self.sent.fetch_add(1, Ordering::Relaxed);
+        if self.is_stopped() || self.max_requests.is_some_and(|max| sent >= max) {
+            self.stop();
+            return Err(OsuApiError::BudgetExhausted);
+        }
+
+        let endpoint = req.uri().path().to_owned();
+        let start = Instant::now();
+
+        let res = tokio::time::timeout(self.timeout, self.client.request(req)).await;
+        let elapsed = start.elapsed();
+
+        let outcome = match &res {
+            Ok(Ok(resp)) => resp.status().as_u16().to_string(),
+            Ok(Err(_)) => "error".to_owned(),
+            Err(_) => "timeout".to_owned(),
+        };
+
+        debug!("{endpoint} {outcome} in {elapsed:.2?}");
+        self.metrics.lock().unwrap().record(outcome, elapsed);
+
+        match res {
+            Ok(res) => Ok(res?),
+            Err(_) => Err(OsuApiError::Timeout),
+        }
+    }
+
+    /// Makes every following request fail with `BudgetExhausted`
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Aggregated timings of requests made so far
+    pub fn stats(&self) -> Option<MetricsSummary> {
+        self.metrics.lock().unwrap().summary()
+    }
+
+    async fn handle_error(res: &mut hyper::Response<Body>) -> ApiResult<Bytes> {
+        let bytes = hyper::body::to_bytes(res.body_mut()).await?;
+        let bytes = decode_body(res.headers(), bytes)?;
+
+        match res.status() {
+            StatusCode::OK => return Ok(bytes),
+            StatusCode::BAD_REQUEST => return Err(OsuApiError::BadRequest),
+            StatusCode::UNAUTHORIZED => return Err(OsuApiError::Unauthorized),
+            StatusCode::TOO_MANY_REQUESTS => {
+                return Err(OsuApiError::RateLimited {
+                    retry_after: header_value(res.headers(), RETRY_AFTER).map(Duration::from_secs),
+                    remaining: header_value(res.headers(), "x-ratelimit-remaining"),
+                })
+            }
+            StatusCode::SERVICE_UNAVAILABLE => return Err(OsuApiError::ServiceUnavailable),
+            _ => (),
+        };
+
+        let err: ApiErrorResponse =
+            serde_json::from_slice(&bytes).map_err(|e| OsuApiError::ParsingError {
+                inner: e,
+                body: bytes.clone(),
+            })?;
+
+        Err(OsuApiError::ApiError { inner: err })
+    }
+
+    async fn parse_bytes<T: DeserializeOwned>(&self, bytes: &Bytes) -> ApiResult<T> {
+        serde_json::from_slice(bytes).map_err(|e| OsuApiError::ParsingError {
+            inner: e,
+            body: bytes.clone(),
+        })
+    }
+
+    async fn request_oauth(&self) -> ApiResult<String> {
+        let data = format!(
+            r#"{{
+            "client_id":"{}",
+            "client_secret":"{}",
+            "grant_type":"client_credentials",
+            "scope":"public" 
+        }}"#,
+            &self.client_id, &self.client_secret
+        );
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("Accept", "application/json")
+            .header(ACCEPT_ENCODING, "gzip, deflate")
+            .header("Content-Type", "application/json")
+            .uri(format!("{}/oauth/token", self.base_url))
+            .body(Body::from(data))?;
+
+        let mut response = self.send(req).await?;
+
+        let bytes = Self::handle_error(&mut response).await?;
+
+        let r: OauthResponse = self.parse_bytes(&bytes).await?;
+
+        Ok(r.access_token)
+    }
+}
+
+/// Decompresses body according to its `Content-Encoding`,
+/// anything besides gzip and deflate is returned as is
+fn decode_body(headers: &HeaderMap, bytes: Bytes) -> ApiResult<Bytes> {
+    let encoding = headers
+        .get(CONTENT_ENCODING)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.trim().to_ascii_lowercase());
+
+    let mut decoded = Vec::new();
+
+    match encoding.as_deref() {
+        Some("gzip") => GzDecoder::new(bytes.as_ref()).read_to_end(&mut decoded)?,
+        Some("deflate") => ZlibDecoder::new(bytes.as_ref()).read_to_end(&mut decoded)?,
+        _ => return Ok(bytes),
+    };
+
+    Ok(decoded.into())
+}
+
+/// Local http server standing in for osu! api in tests
+#[cfg(test)]
+pub(crate) mod mock {
+    use crate::osu_api::{OsuApi, RetryPolicy};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const TOKEN_RESPONSE: &str = r#"{"access_token":"token","token_type":"Bearer","expires_in":86400}"#;
+
+    /// Serves `route` (request path with query to status and body) on a random local
+    /// port and returns base url of the server, oauth requests are always accepted
+    pub async fn serve<F>(route: F) -> String
+    where
+        F: Fn(&str) -> (u16, String) + Send + Sync + 'static,
+    {
+        serve_counted(route).await.0
+    }
+
+    /// Same as [`serve`] but also counts connections opened to the server
+    pub async fn serve_counted<F>(route: F) -> (String, Arc<AtomicUsize>)
+    where
+        F: Fn(&str) -> (u16, String) + Send + Sync + 'static,
+    {
+        let route = Arc::new(route);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&connections);
+
+        let make_service = make_service_fn(move |_| {
+            let route = Arc::clone(&route);
+            counter.fetch_add(1, Ordering::Relaxed);
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let route = Arc::clone(&route);
+
+                    async move {
+                        let path = req.uri().path_and_query().map_or("/", |x| x.as_str());
+
+                        let (status, body) = match path {
+                            "/oauth/token" => (200, TOKEN_RESPONSE.to_owned()),
+                            path => route(path),
+                        };
+
+                        let response = Response::builder().status(status).body(Body::from(body));
+                        Ok::<_, Infallible>(response.unwrap())
+                    }
+                }))
+            }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        (url, connections)
+    }
+
+    /// Api talking to mock server with `route`, retries don't wait
+    pub async fn api<F>(route: F) -> OsuApi
+    where
+        F: Fn(&str) -> (u16, String) + Send + Sync + 'static,
+    {
+        OsuApi::builder()
+            .client_id(1)
+            .client_secret("secret")
+            .base_url(serve(route).await)
+            .retry_policy(RetryPolicy {
+                max_retries: 1,
+                base_delay: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            })
+            .build()
+            .await
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::osu_api::{
+        fetch_ordered, GameMode, MODS_DISPLAY_ORDER, merge_rankings, OsuApi, OsuMods, Ranking, RankingResponse, RankingType,
+        paginate, ranking_link, ranking_pages, required_pages, RankingMetric, RequestPacer, RetryPolicy, WHOLE_RANKING,
+        Score, ScoreType, UserExtended, UserStatistics,
+    };
+    use crate::error::{ApiErrorResponse, OsuApiError};
+    use crate::osu_api::mock;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use hyper::header::CONTENT_ENCODING;
+    use hyper::{Body, StatusCode};
+    use std::env;
+    use std::io::Write;
+    use std::num::NonZeroU32;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use eyre::Result;
+    use dotenv::dotenv;
+
+    fn sample_score() -> Score {
+        score_with(serde_json::json!({}))
+    }
+
+    /// [`sample_score`] with fields of `overrides` replaced, nested objects are merged
+    fn score_with(overrides: serde_json::Value) -> Score {
+        fn merge(target: &mut serde_json::Value, overrides: serde_json::Value) {
+            match (target, overrides) {
+                (serde_json::Value::Object(target), serde_json::Value::Object(overrides)) => {
+                    for (key, value) in overrides {
+                        merge(target.entry(key).or_insert(serde_json::Value::Null), value);
+                    }
+                }
+                (target, value) => *target = value,
+            }
+        }
+
+        let mut score = serde_json::json!({
+            "id": 4321,
+            "best_id": 4321,
+            "user_id": 6892711,
+            "accuracy": 0.9743,
+            "mods": ["HD", "DT"],
+            "score": 12345678,
+            "pp": 512.5,
+            "created_at": "2023-05-15T14:32:01Z",
+            "replay": true,
+            "max_combo": 1200,
+            "statistics": {
+                "count_300": 900,
+                "count_100": 20,
+                "count_50": 1,
+                "count_miss": 0
+            },
+            "beatmapset": {
+                "id": 123,
+                "artist": "Artist",
+                "artist_unicode": "Artist",
+                "creator": "Mapper",
+                "source": "",
+                "title": "Title",
+                "title_unicode": "Title"
+            },
+            "beatmap": {
+                "id": 456,
+                "version": "Insane",
+                "max_combo": 1200
+            }
+        });
+        merge(&mut score, overrides);
+
+        serde_json::from_value(score).unwrap()
+    }
+
+    #[test]
+    fn test_score_links() {
+        let score = sample_score();
+
+        assert_eq!(score.score_link(), "https://osu.ppy.sh/scores/4321");
+        assert_eq!(score.profile_link(), "https://osu.ppy.sh/users/6892711");
+        assert_eq!(score.beatmap_link(), "https://osu.ppy.sh/beatmapsets/123#osu/456");
+    }
+
+    #[test]
+    fn test_mania_score_deserialize() {
+        let score = score_with(serde_json::json!({
+            "mode": "mania",
+            "statistics": { "count_geki": 2400, "count_katu": 60 },
+            "beatmapset": { "id": 77 },
+            "beatmap": { "id": 88, "mode": "mania" }
+        }));
+
+        assert_eq!(score.statistics.count_geki, 2400);
+        assert_eq!(score.statistics.count_katu, 60);
+        assert_eq!(score.mode, GameMode::Mania);
+        assert_eq!(score.beatmap.mode, GameMode::Mania);
+        assert_eq!(score.beatmap_link(), "https://osu.ppy.sh/beatmapsets/77#mania/88");
+
+        // Missing in osu! payloads
+        let score = sample_score();
+        assert_eq!(score.statistics.count_geki, 0);
+        assert_eq!(score.beatmap.mode, GameMode::Osu);
+    }
+
+    #[test]
+    fn test_failed_score_deserialize() {
+        let score = score_with(serde_json::json!({
+            "best_id": null,
+            "pp": null,
+            "passed": false,
+            "perfect": false
+        }));
+
+        assert!(!score.passed);
+        assert!(!score.perfect);
+        assert_eq!(score.best_id, None);
+        assert_eq!(score.pp, None);
+
+        // Best scores may come without the flags
+        let score = sample_score();
+        assert!(score.passed);
+        assert!(!score.perfect);
+    }
+
+    #[test]
+    fn test_beatmap_length_bpm() {
+        let mut score = score_with(serde_json::json!({
+            "pp": 300.0,
+            "beatmap": { "total_length": 180, "bpm": 175.5 }
+        }));
+
+        assert_eq!(score.beatmap.total_length, 180);
+        assert_eq!(score.beatmap.bpm, 175.5);
+
+        // 180s at 1.5x speed is 2 minutes
+        assert_eq!(score.effective_length(), 120.0);
+        assert_eq!(score.pp_per_minute(), Some(150.0));
+
+        score.mods = OsuMods::NIGHTCORE;
+        assert_eq!(score.effective_length(), 120.0);
+
+        score.mods = OsuMods::HALFTIME;
+        assert_eq!(score.effective_length(), 240.0);
+
+        score.mods = OsuMods::HIDDEN;
+        assert_eq!(score.pp_per_minute(), Some(100.0));
+
+        score.pp = None;
+        assert_eq!(score.pp_per_minute(), None);
+
+        // Fields are optional
+        let score = sample_score();
+        assert_eq!(score.beatmap.total_length, 0);
+        assert_eq!(score.pp_per_minute(), None);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_ordered_preserves_order() {
+        // Earlier pages finish last
+        let pages = fetch_ordered(1..=5, 3, |page| async move {
+            tokio::time::sleep(Duration::from_millis(50 / page as u64)).await;
+            Ok(vec![page])
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(pages, vec![vec![1], vec![2], vec![3], vec![4], vec![5]]);
+
+        // Single page leaderboard has nothing left after the first page
+        let pages = 1;
+        let empty: Vec<Vec<i32>> = fetch_ordered(2..=pages, 3, |page| async move { Ok(vec![page]) })
+            .await
+            .unwrap();
+
+        assert!(empty.is_empty());
+
+        // Leaderboard ends earlier than expected
+        let requested = std::sync::atomic::AtomicI32::new(0);
+        let pages = fetch_ordered(1..=10, 1, |page| {
+            requested.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            async move { Ok(if page <= 2 { vec![page] } else { Vec::new() }) }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(pages, vec![vec![1], vec![2]]);
+        assert_eq!(requested.into_inner(), 3);
+    }
+
+    #[test]
+    fn test_score_is_fc() {
+        let mut score = sample_score();
+        assert!(score.is_fc());
+
+        // Sliderbreak
+        score.max_combo = 800;
+        assert!(!score.is_fc());
+
+        // Map max combo is unknown, only misses are checked
+        score.beatmap.max_combo = None;
+        assert!(score.is_fc());
+
+        score.statistics.count_miss = 1;
+        assert!(!score.is_fc());
+    }
+
+    #[test]
+    fn test_score_classic_scoring() {
+        let mut score = sample_score();
+        assert!(score.is_classic_scoring());
+
+        score.mods = serde_json::from_str(r#"["HD", "V2"]"#).unwrap();
+        assert!(!score.is_classic_scoring());
+        assert_eq!(score.mods.to_string(), "HDV2");
+    }
+
+    #[test]
+    fn test_ranking_entry_country() {
+        let stats: UserStatistics = serde_json::from_str(r#"{
+            "pp": 12345.6,
+            "global_rank": 321,
+            "country_rank": 7,
+            "user": {
+                "id": 6892711,
+                "username": "someone",
+                "country_code": "BY",
+                "country": {
+                    "code": "BY",
+                    "name": "Belarus"
+                }
+            }
+        }"#).unwrap();
+
+        assert_eq!(stats.user.country_code, "BY");
+        assert_eq!(stats.user.country.unwrap().name, "Belarus");
+        assert_eq!(stats.country_rank, Some(7));
+
+        let stats: UserStatistics = serde_json::from_str(r#"{
+            "pp": 12345.6,
+            "global_rank": 321,
+            "user": { "id": 6892711, "username": "someone" }
+        }"#).unwrap();
+
+        assert_eq!(stats.country_rank, None);
+        assert_eq!(stats.global_rank, Some(321));
+
+        // Inactive player without rank
+        let stats: UserStatistics = serde_json::from_str(r#"{
+            "global_rank": null,
+            "country_rank": null,
+            "user": { "id": 6892711, "username": "someone" }
+        }"#).unwrap();
+
+        assert_eq!(stats.global_rank, None);
+        assert_eq!(stats.country_rank, None);
+        assert_eq!(stats.pp, 0.0);
+    }
+
+    #[test]
+    fn test_score_type() {
+        assert_eq!("best".parse(), Ok(ScoreType::Best));
+        assert_eq!("Firsts".parse(), Ok(ScoreType::Firsts));
+        assert_eq!("recent".parse(), Ok(ScoreType::Recent { include_fails: false }));
+        assert!("pinned".parse::<ScoreType>().is_err());
+
+        assert_eq!(ScoreType::Recent { include_fails: true }.to_string(), "recent");
+        assert_eq!(ScoreType::Firsts.to_string(), "firsts");
+    }
+
+    #[tokio::test]
+    async fn test_paginate() {
+        // 250 items in total, served in pages of 100
+        let source: Vec<u32> = (0..250).collect();
+        let fetch = |offset: u32| {
+            let page: Vec<u32> = source.iter().copied().skip(offset as usize).take(100).collect();
+            async move { Ok(page) }
+        };
+
+        let all = paginate(100, None, fetch).await.unwrap();
+        assert_eq!(all, source);
+
+        let limited = paginate(100, Some(2), fetch).await.unwrap();
+        assert_eq!(limited.len(), 200);
+
+        // Exactly full last page requires one more (empty) request
+        let requests = std::cell::Cell::new(0);
+        let all = paginate(100, None, |offset| {
+            requests.set(requests.get() + 1);
+            let page: Vec<u32> = source.iter().copied().skip(offset as usize).take(100).collect();
+            async move { Ok(page) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(all.len(), 250);
+        assert_eq!(requests.get(), 3);
+
+        let failing = paginate(100, None, |_| async { Err::<Vec<u32>, _>(OsuApiError::Timeout) }).await;
+        assert!(matches!(failing, Err(OsuApiError::Timeout)));
+    }
+
+    #[test]
+    fn test_ranking_pages() {
+        assert_eq!(ranking_pages(0), 0);
+        assert_eq!(ranking_pages(1), 1);
+        assert_eq!(ranking_pages(50), 1);
+        assert_eq!(ranking_pages(51), 2);
+        assert_eq!(ranking_pages(1000), 20);
+
+        assert_eq!(required_pages(500, 80), 2);
+        assert_eq!(required_pages(100, 10000), 2);
+        assert_eq!(required_pages(WHOLE_RANKING, 10000), 200);
+        assert_eq!(required_pages(WHOLE_RANKING, 1234), 25);
+        assert_eq!(required_pages(WHOLE_RANKING, 0), 0);
+    }
+
+    #[test]
+    fn test_merge_rankings() {
+        let stats = |id: i64, pp: f32, country: &str| -> UserStatistics {
+            serde_json::from_value(serde_json::json!({
+                "pp": pp,
+                "global_rank": 1,
+                "user": { "id": id, "username": format!("user{id}"), "country_code": country }
+            }))
+            .unwrap()
+        };
+
+        let ranking = |users| Ranking { users, total: 0, spotlight: None, pages: 1 };
+
+        let merged = merge_rankings(vec![
+            ranking(vec![stats(1, 100.0, "BY"), stats(2, 300.0, "BY")]),
+            ranking(vec![stats(3, 200.0, "RU"), stats(1, 150.0, "RU")]),
+        ]);
+
+        let ids: Vec<(i64, f32)> = merged.users.iter().map(|x| (x.user.id, x.pp)).collect();
+        assert_eq!(ids, vec![(2, 300.0), (3, 200.0), (1, 150.0)]);
+        assert_eq!(merged.users[2].user.country_code, "RU");
+        assert_eq!(merged.total, 3);
+        assert_eq!(merged.pages, 2);
+    }
+
+    #[test]
+    fn test_ranking_unranked_entry() {
+        let r: RankingResponse = serde_json::from_str(r#"{
+            "ranking": [
+                { "pp": 12345.6, "global_rank": 1, "user": { "id": 1, "username": "ranked" } },
+                { "pp": null, "global_rank": null, "user": { "id": 2, "username": "inactive" } }
+            ],
+            "total": 2
+        }"#).unwrap();
+
+        assert_eq!(r.ranking.len(), 2);
+        assert_eq!(r.ranking[1].user.username, "inactive");
+        assert_eq!(r.ranking[1].global_rank, None);
+        assert_eq!(r.ranking[1].pp, 0.0);
+
+        let user: UserExtended = serde_json::from_str(r#"{
+            "id": 2,
+            "username": "inactive",
+            "statistics": { "pp": null, "global_rank": null }
+        }"#).unwrap();
+
+        let stats = UserStatistics::from(user);
+        assert_eq!(stats.global_rank, None);
+        assert_eq!(stats.pp, 0.0);
+    }
+
+    #[test]
+    fn test_ranking_cache_key() {
+        let country = RankingType::Country { code: "BY".to_owned() };
+        let pp = RankingMetric::Performance;
+        assert_eq!(country.cache_key(GameMode::Osu, pp, 1), "ranking-osu-country-BY-1");
+        assert_eq!(country.cache_key(GameMode::Mania, pp, 3), "ranking-mania-country-BY-3");
+        assert_eq!(RankingType::Global.cache_key(GameMode::Taiko, pp, 2), "ranking-taiko-global-2");
+        assert_eq!(
+            RankingType::Global.cache_key(GameMode::Osu, RankingMetric::Score, 2),
+            "ranking-osu-global-score-2"
+        );
+        assert_eq!(
+            RankingType::Spotlight { id: 271 }.cache_key(GameMode::Fruits, pp, 1),
+            "ranking-fruits-spotlight-271-1"
+        );
+    }
+
+    #[test]
+    fn test_ranking_metric() {
+        assert_eq!("pp".parse(), Ok(RankingMetric::Performance));
+        assert_eq!("Score".parse(), Ok(RankingMetric::Score));
+        assert!("accuracy".parse::<RankingMetric>().is_err());
+
+        let link = |ranking: &RankingType, metric| {
+            ranking_link("https://osu.ppy.sh", GameMode::Osu, ranking, metric, 2)
+        };
+
+        assert_eq!(
+            link(&RankingType::Global, RankingMetric::Performance),
+            "https://osu.ppy.sh/api/v2/rankings/osu/performance?cursor[page]=2"
+        );
+        assert_eq!(
+            link(&RankingType::Global, RankingMetric::Score),
+            "https://osu.ppy.sh/api/v2/rankings/osu/score?cursor[page]=2"
+        );
+        assert_eq!(
+            link(&RankingType::Spotlight { id: 271 }, RankingMetric::Score),
+            "https://osu.ppy.sh/api/v2/rankings/osu/charts?spotlight=271"
+        );
+    }
+
+    #[test]
+    fn test_spotlight_ranking_deserialize() {
+        let r: RankingResponse = serde_json::from_str(r#"{
+            "ranking": [],
+            "spotlight": {
+                "id": 271,
+                "name": "Summer 2023",
+                "start_date": "2023-06-01T00:00:00+00:00",
+                "end_date": "2023-08-31T00:00:00+00:00",
+                "mode_specific": true,
+                "type": "seasonal"
+            }
+        }"#).unwrap();
+
+        assert!(r.total.is_none());
+
+        let spotlight = r.spotlight.unwrap();
+        assert_eq!(spotlight.id, 271);
+        assert_eq!(spotlight.name, "Summer 2023");
+        assert!(spotlight.participant_count.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_headers() {
+        let mut resp = hyper::Response::builder()
+            .status(429)
+            .header("Retry-After", "5")
+            .header("X-RateLimit-Remaining", "0")
+            .body(hyper::Body::empty())
+            .unwrap();
+
+        let err = OsuApi::handle_error(&mut resp).await.unwrap_err();
+
+        assert!(matches!(
+            err,
+            OsuApiError::RateLimited {
+                retry_after: Some(after),
+                remaining: Some(0),
+            } if after == Duration::from_secs(5)
+        ));
+
+        let mut resp = hyper::Response::builder()
+            .status(429)
+            .body(hyper::Body::empty())
+            .unwrap();
+
+        let err = OsuApi::handle_error(&mut resp).await.unwrap_err();
+
+        assert!(matches!(
+            err,
+            OsuApiError::RateLimited { retry_after: None, remaining: None }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_replay_status() {
+        let response = |status: u16| {
+            hyper::Response::builder()
+                .status(status)
+                .body(hyper::Body::from("replay bytes"))
+                .unwrap()
+        };
+
+        assert!(OsuApi::replay_status(&mut response(200)).await.unwrap());
+        assert!(!OsuApi::replay_status(&mut response(404)).await.unwrap());
+
+        assert!(matches!(
+            OsuApi::replay_status(&mut response(429)).await,
+            Err(OsuApiError::RateLimited { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_validate_response() {
+        let api = mock::api(|path| match path {
+            p if p.starts_with("/api/v2/rankings/osu/performance") => {
+                (200, r#"{"ranking":[],"total":0}"#.to_owned())
+            }
+            _ => (404, r#"{"error":"not found"}"#.to_owned()),
+        })
+        .await;
+        assert!(api.validate().await.is_ok());
+
+        // Bad client secret on /oauth/token and bad token anywhere else
+        for body in [r#"{"error":"invalid_client"}"#, r#"{"authentication":"basic"}"#] {
+            let api = mock::api(move |_| (401, body.to_owned())).await;
+
+            let err = api.validate().await.unwrap_err();
+            assert!(matches!(err, OsuApiError::Unauthorized));
+            assert!(!err.is_retryable());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_is_replay_available() {
+        let flaky_calls = AtomicUsize::new(0);
+
+        let api = mock::api(move |path| match path {
+            "/api/v2/scores/osu/1/download" => (200, "replay bytes".to_owned()),
+            "/api/v2/scores/osu/2/download" => (404, r#"{"error":"not found"}"#.to_owned()),
+            // Client credentials tokens can't download replays
+            "/api/v2/scores/osu/3/download" => (403, r#"{"error":"forbidden"}"#.to_owned()),
+            "/api/v2/scores/osu/5/download" if flaky_calls.fetch_add(1, Ordering::Relaxed) == 0 => {
+                (503, r#"{"error":"unavailable"}"#.to_owned())
+            }
+            "/api/v2/scores/osu/5/download" => (200, "replay bytes".to_owned()),
+            _ => (401, r#"{"authentication":"basic"}"#.to_owned()),
+        })
+        .await;
+
+        assert!(api.is_replay_available(GameMode::Osu, 1).await.unwrap());
+        assert!(!api.is_replay_available(GameMode::Osu, 2).await.unwrap());
+        assert!(!api.is_replay_available(GameMode::Osu, 3).await.unwrap());
+
+        // Goes through the same retries as every other request
+        assert!(api.is_replay_available(GameMode::Osu, 5).await.unwrap());
+        assert_eq!(api.stats().unwrap().recovered, 1);
+
+        assert!(matches!(
+            api.is_replay_available(GameMode::Osu, 4).await,
+            Err(OsuApiError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_secs(1),
+            max_retry_after: Duration::from_secs(60),
+        };
+        let rate_limited = |secs| OsuApiError::RateLimited {
+            retry_after: Some(Duration::from_secs(secs)),
+            remaining: None,
+        };
+
+        assert_eq!(policy.retry_delay(&OsuApiError::Timeout, 2), Some(Duration::from_secs(4)));
+        // Cap doesn't depend on backoff schedule
+        assert_eq!(policy.retry_delay(&rate_limited(60), 0), Some(Duration::from_secs(60)));
+
+        // Waiting longer than the policy allows fails the request right away
+        assert_eq!(policy.retry_delay(&rate_limited(61), 0), None);
+        assert_eq!(policy.retry_delay(&rate_limited(86400), 0), None);
+
+        // Huge --max-retries doesn't overflow
+        let policy = RetryPolicy { max_retries: u32::MAX, ..policy };
+        assert_eq!(policy.retry_delay(&OsuApiError::Timeout, 40), Some(Duration::from_secs(u32::MAX.into())));
+        assert_eq!(policy.retry_delay(&rate_limited(30), 0), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_request_pacer() {
+        let pacer = RequestPacer::new(NonZeroU32::new(120).unwrap());
+
+        let first = pacer.reserve();
+        let second = pacer.reserve();
+        let third = pacer.reserve();
+
+        assert_eq!(second - first, Duration::from_millis(500));
+        assert_eq!(third - second, Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_compressed_response() {
+        let json = br#"{"error": "compressed"}"#;
+
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(json).unwrap();
+
+        let mut deflate = ZlibEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(json).unwrap();
+
+        for (encoding, body) in [
+            (Some("gzip"), gzip.finish().unwrap()),
+            (Some("deflate"), deflate.finish().unwrap()),
+            (None, json.to_vec()),
+        ] {
+            let mut builder = hyper::Response::builder().status(StatusCode::OK);
+            if let Some(encoding) = encoding {
+                builder = builder.header(CONTENT_ENCODING, encoding);
+            }
+            let mut resp = builder.body(Body::from(body)).unwrap();
+
+            let bytes = OsuApi::handle_error(&mut resp).await.unwrap();
+            let parsed: ApiErrorResponse = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(parsed.error, "compressed");
+        }
+
+        let mut resp = hyper::Response::builder()
+            .header(CONTENT_ENCODING, "gzip")
+            .body(Body::from("not gzip"))
+            .unwrap();
+        assert!(matches!(
+            OsuApi::handle_error(&mut resp).await,
+            Err(OsuApiError::DecodingError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_mods_strict_parsing() {
+        assert_eq!(OsuMods::from_str_strict("hddt").unwrap(), OsuMods::HIDDEN | OsuMods::DOUBLETIME);
+        assert_eq!(OsuMods::from_str_strict("NM").unwrap(), OsuMods::empty());
+
+        match OsuMods::from_str_strict("HDXZDTQQ") {
+            Err(OsuApiError::UnknownMods(unknown)) => assert_eq!(unknown, vec!["XZ", "QQ"]),
+            other => panic!("expected UnknownMods, got {other:?}"),
+        }
+
+        // Leftover letter isn't silently dropped
+        let err = OsuMods::from_str_strict("HDH").unwrap_err();
+        assert!(err.to_string().contains("\"H\""), "{err}");
+        assert_eq!("HDH".parse::<OsuMods>().unwrap(), OsuMods::HIDDEN);
+
+        // Lenient parsing skips unknown acronyms
+        assert_eq!("XZ".parse::<OsuMods>().unwrap(), OsuMods::empty());
+        assert_eq!("HDXZ".parse::<OsuMods>().unwrap(), OsuMods::HIDDEN);
+    }
+
+    #[test]
+    fn test_mods_display_order() {
+        let display = |s: &str| s.parse::<OsuMods>().unwrap().to_string();
+
+        assert_eq!(display("HDDT"), "HDDT");
+        assert_eq!(display("DTHD"), "HDDT");
+        assert_eq!(display("HRHD"), "HDHR");
+        assert_eq!(display("HDEZ"), "EZHD");
+        assert_eq!(display("NCHD"), "HDNC");
+        assert_eq!(display("PFHRHD"), "HDHRPF");
+        assert_eq!(display("FLHDHRDTNF"), "NFHDHRDTFL");
+        assert_eq!(display("MRV2"), "V2MR");
+        assert_eq!(display(""), "NM");
+    }
+
+    #[test]
+    fn test_mods_round_trip() {
+        let mut all = OsuMods::empty();
+
+        for (mods, acronym) in MODS_DISPLAY_ORDER {
+            assert_eq!(mods.to_string(), acronym);
+            assert_eq!(acronym.parse::<OsuMods>().unwrap(), mods, "{acronym}");
+            assert_eq!(OsuMods::from_str_strict(acronym).unwrap(), mods, "{acronym}");
+            assert_eq!(serde_json::from_str::<OsuMods>(&format!("[\"{acronym}\"]")).unwrap(), mods);
+
+            all |= mods;
+        }
+
+        // Every defined mod has an acronym
+        assert_eq!(all, OsuMods::all());
+        assert_eq!(all.to_string().parse::<OsuMods>().unwrap(), all);
+        assert_eq!("HDMR".parse::<OsuMods>().unwrap(), OsuMods::HIDDEN | OsuMods::MIRROR);
+    }
+
+    #[test]
+    fn test_mods_try_from_bits() {
+        let mods = OsuMods::try_from(8 | 64).unwrap();
+        assert_eq!(mods.bits(), (OsuMods::HIDDEN | OsuMods::DOUBLETIME).bits());
+
+        assert!(matches!(
+            OsuMods::try_from(1 << 3 | 1 << 11),
+            Err(OsuApiError::UnknownModBits(2056))
+        ));
+
+        let mods: OsuMods = serde_json::from_str("24").unwrap();
+        assert_eq!(mods.bits(), (OsuMods::HIDDEN | OsuMods::HARDROCK).bits());
+
+        assert!(serde_json::from_str::<OsuMods>("2048").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_limit() -> Result<()> {
+        dotenv()?;
+
+        let api = OsuApi::new(
+            env::var("CLIENT_ID")?.parse()?,
+            env::var("CLIENT_SECRET")?.as_str(),
+        )
+        .await?;
+
+        let ranking = RankingType::Country{ code: "by".to_owned() };
+
+        let lb = api.get_ranking(GameMode::Osu, ranking, RankingMetric::Performance, 100).await?;
+
+        assert_eq!(lb.users.len(), 100);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_user_best_scores_health() -> Result<()> {
+        dotenv()?;
+
+        let api = OsuApi::new(
+            env::var("CLIENT_ID")?.parse()?,
             env::var("CLIENT_SECRET")?.as_str(),
         )
         .await?;
 
-        api.get_user_best_scores(6892711).await?;
+        api.get_user_scores(6892711, GameMode::Osu, ScoreType::Best, 100).await?;
 
         // Edge case: with null pp's
-        api.get_user_best_scores(32743279).await?;
+        api.get_user_scores(32743279, GameMode::Osu, ScoreType::Best, 100).await?;
 
         // Edge case: lazer scores in top100
-        api.get_user_best_scores(6716499).await?;
+        api.get_user_scores(6716499, GameMode::Osu, ScoreType::Best, 100).await?;
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_connection_pool() {
+        // Oauth request plus 4 sequential ones, returns amount of opened connections
+        async fn connections(max_idle_per_host: usize) -> usize {
+            let (url, connections) = mock::serve_counted(|_| (200, "[]".to_owned())).await;
+
+            let api = OsuApi::builder()
+                .client_id(1)
+                .client_secret("secret")
+                .base_url(url)
+                .max_idle_per_host(max_idle_per_host)
+                .keep_alive(Duration::from_secs(60))
+                .pool_idle_timeout(Duration::from_secs(90))
+                .build()
+                .await
+                .unwrap();
+
+            for id in 1..=4 {
+                api.get_user_scores(id, GameMode::Osu, ScoreType::Best, 5).await.unwrap();
+            }
+
+            connections.load(Ordering::Relaxed)
+        }
+
+        // Nothing is pooled, every request opens its own connection
+        assert_eq!(connections(0).await, 5);
+        assert!(connections(1).await < 5);
+    }
 }
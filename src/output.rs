@@ -0,0 +1,1175 @@
+use clap::ValueEnum;
+use eyre::Result;
+use serde::de::DeserializeOwned;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Missing fields are defaulted when reading back output with `--columns`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Output {
+    pub username: String,
+    /// Stays the same after username changes
+    pub user_id: i64,
+    pub profile_link: String,
+    pub country: String,
+    /// Empty for unranked and failed scores
+    pub pp: Option<f32>,
+    /// Contribution to user's total pp, filled only for best scores
+    pub weighted_pp: Option<f32>,
+    pub accuracy: String,
+    pub total_score: i64,
+    /// ScoreV2 totals aren't comparable with v1 ones
+    pub scorev2: bool,
+    pub date: String,
+    pub replay: bool,
+    /// Filled only when replays are checked
+    pub replay_available: Option<bool>,
+    pub score_link: String,
+    pub beatmap_link: String,
+    pub map: String,
+    pub diff: String,
+    pub length_seconds: u32,
+    pub bpm: f32,
+    /// pp over map length (sped up or slowed down by DT/HT) in minutes
+    pub pp_per_minute: Option<f32>,
+    pub mods: String,
+    pub max_combo: u32,
+    /// Only filled for mania scores
+    pub count_geki: Option<u32>,
+    pub count_katu: Option<u32>,
+    pub is_fc: bool,
+    pub passed: bool,
+    pub perfect: bool,
+    pub country_rank: Option<i32>,
+    /// Empty for players without rank
+    pub global_rank: Option<i32>,
+    pub total_pp: f32,
+}
+
+impl Output {
+    /// Copy with pp values rounded to `precision` decimal places
+    pub fn rounded(&self, precision: usize) -> Output {
+        let round = |x: f32| {
+            let factor = 10f64.powi(precision as i32);
+            ((x as f64 * factor).round() / factor) as f32
+        };
+
+        Output {
+            pp: self.pp.map(round),
+            weighted_pp: self.weighted_pp.map(round),
+            pp_per_minute: self.pp_per_minute.map(round),
+            total_pp: round(self.total_pp),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    Csv,
+    Markdown,
+    Json,
+    Jsonl,
+    Html,
+}
+
+impl OutputFormat {
+    pub fn default_path(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "output.csv",
+            OutputFormat::Markdown => "output.md",
+            OutputFormat::Json => "output.json",
+            OutputFormat::Jsonl => "output.jsonl",
+            OutputFormat::Html => "output.html",
+        }
+    }
+
+    /// Whether output can be restricted with `--columns`
+    pub fn supports_columns(&self) -> bool {
+        matches!(self, OutputFormat::Csv | OutputFormat::Json | OutputFormat::Jsonl)
+    }
+}
+
+/// Validates output column name, it should match
+/// serialized name of an `Output` field
+pub fn parse_column(s: &str) -> Result<String> {
+    let known = serde_json::to_value(Output::default())?;
+    let column = s.trim();
+
+    if known.get(column).is_none() {
+        let available: Vec<&str> = known
+            .as_object()
+            .map(|x| x.keys().map(String::as_str).collect())
+            .unwrap_or_default();
+
+        eyre::bail!("unknown column {column}, available: {}", available.join(", "));
+    }
+
+    Ok(column.to_owned())
+}
+
+/// `serde_json::to_value` widens f32 fields to f64 (412.34 turns into
+/// 412.3399963378906), going through the text keeps the short form
+fn row_value(row: &Output) -> Result<Value> {
+    Ok(serde_json::from_str(&serde_json::to_string(row)?)?)
+}
+
+/// Picks `columns` (in that order) out of serialized row
+pub fn select_columns(row: &Output, columns: &[String]) -> Result<Vec<(String, Value)>> {
+    let mut value = row_value(row)?;
+
+    Ok(columns
+        .iter()
+        .map(|column| (column.clone(), value[column.as_str()].take()))
+        .collect())
+}
+
+/// Row with only selected columns, keys are serialized in `columns`
+/// order (`serde_json::Map` would sort them)
+struct SelectedRow(Vec<(String, Value)>);
+
+impl Serialize for SelectedRow {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (column, value) in &self.0 {
+            map.serialize_entry(column, value)?;
+        }
+        map.end()
+    }
+}
+
+/// Whole row or the selected columns of it
+#[derive(Serialize)]
+#[serde(untagged)]
+enum JsonRow {
+    Full(Value),
+    Selected(SelectedRow),
+}
+
+impl JsonRow {
+    fn new(row: &Output, columns: Option<&[String]>) -> Result<Self> {
+        Ok(match columns {
+            Some(columns) => JsonRow::Selected(SelectedRow(select_columns(row, columns)?)),
+            None => JsonRow::Full(row_value(row)?),
+        })
+    }
+}
+
+fn value_to_field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Output format backend, rows are passed one by one as they're found
+pub trait Writer: Send {
+    fn write_row(&mut self, row: &Output) -> Result<()>;
+
+    /// Writes everything that's buffered, called once all rows are written
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+pub fn create_writer(
+    format: OutputFormat,
+    out: Box<dyn Write + Send>,
+    columns: Option<Vec<String>>,
+) -> Result<Box<dyn Writer>> {
+    if columns.is_some() && !format.supports_columns() {
+        eyre::bail!("--columns is not supported by {format:?} format")
+    }
+
+    let writer: Box<dyn Writer> = match format {
+        OutputFormat::Csv => Box::new(CsvWriter::new(out, columns, true)),
+        OutputFormat::Markdown => Box::new(MarkdownWriter::new(out)),
+        OutputFormat::Json => Box::new(JsonWriter::new(out, Vec::new(), columns)),
+        OutputFormat::Jsonl => Box::new(JsonlWriter::new(out, columns)),
+        OutputFormat::Html => Box::new(HtmlWriter::new(out)),
+    };
+
+    Ok(writer)
+}
+
+/// Opens output file, with `append` rows are added to existing file
+/// (csv header isn't repeated, json array is extended) and rows already
+/// in it are skipped
+pub fn open_output(
+    path: &Path,
+    format: OutputFormat,
+    columns: Option<Vec<String>>,
+    append: bool,
+) -> Result<Box<dyn Writer>> {
+    if !append {
+        return create_writer(format, Box::new(File::create(path)?), columns);
+    }
+
+    let has_content = path.metadata().map(|x| x.len() > 0).unwrap_or(false);
+    let append_file = || OpenOptions::new().create(true).append(true).open(path);
+
+    if format == OutputFormat::Csv && has_content {
+        check_csv_header(path, columns.as_deref())?;
+    }
+
+    let writer: Box<dyn Writer> = match format {
+        OutputFormat::Csv => Box::new(DedupWriter {
+            seen: existing_csv_links(path)?,
+            inner: CsvWriter::new(append_file()?, columns, !has_content),
+        }),
+        OutputFormat::Jsonl => {
+            let rows = read_jsonl(path)?;
+
+            Box::new(DedupWriter {
+                seen: score_links(&rows),
+                inner: JsonlWriter::new(append_file()?, columns),
+            })
+        }
+        // Whole array is rewritten with new rows added to the end, into a temporary
+        // file that replaces the original one only after all rows are written
+        OutputFormat::Json => {
+            let rows = read_json(path)?;
+            let tmp = tmp_path(path);
+
+            Box::new(ReplaceOnFinish {
+                inner: DedupWriter {
+                    seen: score_links(&rows),
+                    inner: JsonWriter::new(File::create(&tmp)?, rows, columns),
+                },
+                tmp,
+                path: path.to_owned(),
+            })
+        }
+        OutputFormat::Markdown | OutputFormat::Html => {
+            eyre::bail!("--append is not supported by {format:?} format")
+        }
+    };
+
+    Ok(writer)
+}
+
+/// `out.json` -> `out.json.tmp` in the same directory, so renaming it stays on one filesystem
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".tmp");
+
+    path.with_file_name(name)
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    match std::fs::read(path) {
+        Ok(bytes) if !bytes.is_empty() => Ok(serde_json::from_slice(&bytes)?),
+        Ok(_) => Ok(Vec::new()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn read_jsonl<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut rows = Vec::new();
+    for line in content.lines().filter(|x| !x.trim().is_empty()) {
+        rows.push(serde_json::from_str(line)?);
+    }
+
+    Ok(rows)
+}
+
+/// Reads rows of previously written csv, json or jsonl output (picked by extension)
+pub fn read_rows(path: &Path) -> Result<Vec<Output>> {
+    if !path.exists() {
+        eyre::bail!("{} doesn't exist", path.display());
+    }
+
+    match path.extension().and_then(|x| x.to_str()) {
+        Some("csv") => {
+            let mut reader = csv::Reader::from_path(path)?;
+            let mut rows = Vec::new();
+
+            for row in reader.deserialize() {
+                rows.push(row?);
+            }
+
+            Ok(rows)
+        }
+        Some("json") => read_json(path),
+        Some("jsonl" | "ndjson") => read_jsonl(path),
+        _ => eyre::bail!("can't guess format of {}, expected .csv, .json or .jsonl", path.display()),
+    }
+}
+
+fn score_links(rows: &[Value]) -> HashSet<String> {
+    rows.iter()
+        .filter_map(|row| row.get("score_link")?.as_str())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Header that's written for `columns`, every field if it's `None`
+fn csv_header(columns: Option<&[String]>) -> Result<Vec<String>> {
+    if let Some(columns) = columns {
+        return Ok(columns.to_vec());
+    }
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.serialize(Output::default())?;
+    let bytes = writer.into_inner()?;
+
+    let mut reader = csv::Reader::from_reader(bytes.as_slice());
+    Ok(reader.headers()?.iter().map(str::to_owned).collect())
+}
+
+/// Appended rows have to line up with the ones already in the file,
+/// which could be written with other `--columns` or by older version
+fn check_csv_header(path: &Path, columns: Option<&[String]>) -> Result<()> {
+    let existing: Vec<String> = csv::Reader::from_path(path)?
+        .headers()?
+        .iter()
+        .map(str::to_owned)
+        .collect();
+    let expected = csv_header(columns)?;
+
+    if existing != expected {
+        eyre::bail!(
+            "can't append to {}, its columns ({}) differ from the ones being written ({})",
+            path.display(),
+            existing.join(","),
+            expected.join(",")
+        );
+    }
+
+    Ok(())
+}
+
+/// Score links of rows in existing csv file, empty if there's
+/// no such file or it has no `score_link` column
+fn existing_csv_links(path: &Path) -> Result<HashSet<String>> {
+    if !path.exists() {
+        return Ok(HashSet::new());
+    }
+
+    let mut reader = csv::Reader::from_path(path)?;
+
+    let Some(index) = reader.headers()?.iter().position(|x| x == "score_link") else {
+        return Ok(HashSet::new());
+    };
+
+    let mut links = HashSet::new();
+    for record in reader.records() {
+        if let Some(link) = record?.get(index) {
+            links.insert(link.to_owned());
+        }
+    }
+
+    Ok(links)
+}
+
+/// Rounds rows before they're written, markdown and html are left as is
+/// since they sort rows by full precision pp and round it themselves
+pub fn with_precision(writer: Box<dyn Writer>, format: OutputFormat, precision: usize) -> Box<dyn Writer> {
+    match format {
+        OutputFormat::Markdown | OutputFormat::Html => writer,
+        _ => Box::new(RoundingWriter { inner: writer, precision }),
+    }
+}
+
+struct RoundingWriter {
+    inner: Box<dyn Writer>,
+    precision: usize,
+}
+
+impl Writer for RoundingWriter {
+    fn write_row(&mut self, row: &Output) -> Result<()> {
+        self.inner.write_row(&row.rounded(self.precision))
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+/// Keeps only the highest pp row of every beatmap if `enabled`,
+/// rows are buffered until `finish` then
+pub fn unique_beatmaps(writer: Box<dyn Writer>, enabled: bool) -> Box<dyn Writer> {
+    match enabled {
+        true => Box::new(UniqueBeatmapWriter {
+            inner: writer,
+            rows: Vec::new(),
+            positions: HashMap::new(),
+        }),
+        false => writer,
+    }
+}
+
+struct UniqueBeatmapWriter {
+    inner: Box<dyn Writer>,
+    rows: Vec<Output>,
+    /// Beatmap link to its row in `rows`
+    positions: HashMap<String, usize>,
+}
+
+impl Writer for UniqueBeatmapWriter {
+    fn write_row(&mut self, row: &Output) -> Result<()> {
+        // Rows read back from output written without this column would all collapse into one
+        if row.beatmap_link.is_empty() {
+            eyre::bail!(
+                "can't pick unique beatmaps, beatmap_link is missing for {}'s score (was it left out with --columns?)",
+                row.username
+            );
+        }
+
+        match self.positions.get(&row.beatmap_link) {
+            Some(&pos) => {
+                if row.pp.unwrap_or(0.0) > self.rows[pos].pp.unwrap_or(0.0) {
+                    self.rows[pos] = row.clone();
+                }
+            }
+            None => {
+                self.positions.insert(row.beatmap_link.clone(), self.rows.len());
+                self.rows.push(row.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        for row in &self.rows {
+            self.inner.write_row(row)?;
+        }
+
+        self.inner.finish()
+    }
+}
+
+/// Skips rows whose score link was already written
+struct DedupWriter<W: Writer> {
+    inner: W,
+    seen: HashSet<String>,
+}
+
+impl<W: Writer> Writer for DedupWriter<W> {
+    fn write_row(&mut self, row: &Output) -> Result<()> {
+        if self.seen.insert(row.score_link.clone()) {
+            self.inner.write_row(row)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        Box::new(self.inner).finish()
+    }
+}
+
+/// Writes into `tmp` and moves it over `path` on finish, a run
+/// that fails halfway leaves `path` untouched
+struct ReplaceOnFinish<W: Writer> {
+    inner: W,
+    tmp: PathBuf,
+    path: PathBuf,
+}
+
+impl<W: Writer> Writer for ReplaceOnFinish<W> {
+    fn write_row(&mut self, row: &Output) -> Result<()> {
+        self.inner.write_row(row)
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        Box::new(self.inner).finish()?;
+        std::fs::rename(&self.tmp, &self.path)?;
+
+        Ok(())
+    }
+}
+
+pub struct CsvWriter<W: Write> {
+    inner: csv::Writer<W>,
+    columns: Option<Vec<String>>,
+    header_written: bool,
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Writes every column if `columns` is `None`
+    pub fn new(out: W, columns: Option<Vec<String>>, header: bool) -> Self {
+        Self {
+            inner: csv::WriterBuilder::new().has_headers(header).from_writer(out),
+            columns,
+            header_written: !header,
+        }
+    }
+}
+
+impl<W: Write + Send> Writer for CsvWriter<W> {
+    fn write_row(&mut self, row: &Output) -> Result<()> {
+        let Some(columns) = &self.columns else {
+            self.inner.serialize(row)?;
+            return Ok(());
+        };
+
+        if !self.header_written {
+            self.inner.write_record(columns)?;
+            self.header_written = true;
+        }
+
+        let record: Vec<String> = select_columns(row, columns)?
+            .iter()
+            .map(|(_, value)| value_to_field(value))
+            .collect();
+
+        self.inner.write_record(&record)?;
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+/// Single json array, rows are buffered until `finish`
+pub struct JsonWriter<W: Write> {
+    out: W,
+    rows: Vec<JsonRow>,
+    columns: Option<Vec<String>>,
+}
+
+impl<W: Write> JsonWriter<W> {
+    /// `rows` are written before the new ones, every
+    /// field is written if `columns` is `None`
+    pub fn new(out: W, rows: Vec<Value>, columns: Option<Vec<String>>) -> Self {
+        Self {
+            out,
+            rows: rows.into_iter().map(JsonRow::Full).collect(),
+            columns,
+        }
+    }
+}
+
+impl<W: Write + Send> Writer for JsonWriter<W> {
+    fn write_row(&mut self, row: &Output) -> Result<()> {
+        self.rows.push(JsonRow::new(row, self.columns.as_deref())?);
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        serde_json::to_writer_pretty(&mut self.out, &self.rows)?;
+        writeln!(self.out)?;
+        self.out.flush()?;
+
+        Ok(())
+    }
+}
+
+/// One json object per line
+pub struct JsonlWriter<W: Write> {
+    out: W,
+    columns: Option<Vec<String>>,
+}
+
+impl<W: Write> JsonlWriter<W> {
+    /// Writes every field if `columns` is `None`
+    pub fn new(out: W, columns: Option<Vec<String>>) -> Self {
+        Self { out, columns }
+    }
+}
+
+impl<W: Write + Send> Writer for JsonlWriter<W> {
+    fn write_row(&mut self, row: &Output) -> Result<()> {
+        match &self.columns {
+            Some(columns) => {
+                let row = SelectedRow(select_columns(row, columns)?);
+                serde_json::to_writer(&mut self.out, &row)?;
+            }
+            None => serde_json::to_writer(&mut self.out, row)?,
+        }
+        writeln!(self.out)?;
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+const MARKDOWN_COLUMNS: [(&str, bool); 7] = [
+    ("username", false),
+    ("pp", true),
+    ("map", false),
+    ("diff", false),
+    ("mods", false),
+    ("date", false),
+    ("country_rank", true),
+];
+
+/// Pipe-table writer, rows are buffered (and sorted by pp) until
+/// `finish` since column widths depend on every row
+pub struct MarkdownWriter<W: Write> {
+    out: W,
+    rows: Vec<(f32, [String; 7])>,
+}
+
+impl<W: Write> MarkdownWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            rows: Vec::new(),
+        }
+    }
+}
+
+fn format_pp(pp: Option<f32>) -> String {
+    pp.map(|x| format!("{x:.2}")).unwrap_or_default()
+}
+
+fn escape_markdown(s: &str) -> String {
+    s.replace('|', "\\|")
+}
+
+impl<W: Write + Send> Writer for MarkdownWriter<W> {
+    fn write_row(&mut self, row: &Output) -> Result<()> {
+        self.rows.push((
+            row.pp.unwrap_or(0.0),
+            [
+                escape_markdown(&row.username),
+                format_pp(row.pp),
+                escape_markdown(&row.map),
+                escape_markdown(&row.diff),
+                row.mods.clone(),
+                row.date.clone(),
+                row.country_rank.map(|x| x.to_string()).unwrap_or_default(),
+            ],
+        ));
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.rows.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut widths = MARKDOWN_COLUMNS.map(|(name, _)| name.len().max(3));
+        for (_, cells) in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(cells) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let header = MARKDOWN_COLUMNS.map(|(name, _)| name.to_string());
+        write_markdown_row(&mut self.out, &header, &widths)?;
+
+        let separator: Vec<String> = MARKDOWN_COLUMNS
+            .iter()
+            .zip(widths)
+            .map(|(&(_, numeric), width)| match numeric {
+                true => format!("{}:", "-".repeat(width - 1)),
+                false => "-".repeat(width),
+            })
+            .collect();
+        writeln!(self.out, "| {} |", separator.join(" | "))?;
+
+        for (_, cells) in &self.rows {
+            write_markdown_row(&mut self.out, cells, &widths)?;
+        }
+
+        self.out.flush()?;
+
+        Ok(())
+    }
+}
+
+fn write_markdown_row<W: Write>(out: &mut W, cells: &[String; 7], widths: &[usize; 7]) -> Result<()> {
+    let padded: Vec<String> = cells
+        .iter()
+        .zip(widths)
+        .zip(MARKDOWN_COLUMNS)
+        .map(|((cell, &width), (_, numeric))| match numeric {
+            true => format!("{cell:>width$}"),
+            false => format!("{cell:<width$}"),
+        })
+        .collect();
+
+    writeln!(out, "| {} |", padded.join(" | "))?;
+
+    Ok(())
+}
+
+/// Standalone html page with a table sortable by clicking on its header
+pub struct HtmlWriter<W: Write> {
+    out: W,
+    rows: Vec<Output>,
+}
+
+impl<W: Write> HtmlWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            rows: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write + Send> Writer for HtmlWriter<W> {
+    fn write_row(&mut self, row: &Output) -> Result<()> {
+        self.rows.push(row.clone());
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.rows.sort_by(|a, b| b.pp.unwrap_or(0.0).total_cmp(&a.pp.unwrap_or(0.0)));
+
+        self.out.write_all(render_html(&self.rows).as_bytes())?;
+        self.out.flush()?;
+
+        Ok(())
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+const HTML_HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Top scores</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { padding: 4px 8px; border-bottom: 1px solid #ddd; text-align: left; }
+th { cursor: pointer; background: #f3f3f3; user-select: none; }
+td.num { text-align: right; }
+tr:hover td { background: #fafafa; }
+</style>
+</head>
+<body>
+"#;
+
+// Numeric columns are compared by value, everything else as text
+const HTML_SCRIPT: &str = r#"<script>
+document.querySelectorAll("th").forEach((th, index) => {
+  let asc = false;
+  th.addEventListener("click", () => {
+    const body = th.closest("table").tBodies[0];
+    const numeric = th.dataset.numeric !== undefined;
+    const key = (row) => row.cells[index].dataset.value ?? row.cells[index].textContent;
+    asc = !asc;
+    [...body.rows]
+      .sort((a, b) => {
+        const [x, y] = [key(a), key(b)];
+        const order = numeric ? x - y : x.localeCompare(y);
+        return asc ? order : -order;
+      })
+      .forEach((row) => body.appendChild(row));
+  });
+});
+</script>
+</body>
+</html>
+"#;
+
+const HTML_COLUMNS: [(&str, bool); 9] = [
+    ("username", false),
+    ("country", false),
+    ("pp", true),
+    ("accuracy", true),
+    ("map", false),
+    ("diff", false),
+    ("mods", false),
+    ("date", false),
+    ("score", false),
+];
+
+/// Renders `rows` (in given order) into a standalone html page
+fn render_html(rows: &[Output]) -> String {
+    let mut html = String::from(HTML_HEAD);
+
+    html.push_str("<table>\n<thead>\n<tr>");
+    for (name, numeric) in HTML_COLUMNS {
+        match numeric {
+            true => html.push_str(&format!("<th data-numeric>{name}</th>")),
+            false => html.push_str(&format!("<th>{name}</th>")),
+        }
+    }
+    html.push_str("</tr>\n</thead>\n<tbody>\n");
+
+    for row in rows {
+        let accuracy = row.accuracy.trim_end_matches('%');
+
+        html.push_str(&format!(
+            concat!(
+                "<tr><td>{}</td><td>{}</td>",
+                "<td class=\"num\" data-value=\"{}\">{}</td>",
+                "<td class=\"num\" data-value=\"{}\">{}</td>",
+                "<td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td>",
+                "<td><a href=\"{}\">link</a></td></tr>\n"
+            ),
+            escape_html(&row.username),
+            escape_html(&row.country),
+            row.pp.unwrap_or(0.0),
+            format_pp(row.pp),
+            escape_html(accuracy),
+            escape_html(&row.accuracy),
+            escape_html(&row.beatmap_link),
+            escape_html(&row.map),
+            escape_html(&row.diff),
+            escape_html(&row.mods),
+            escape_html(&row.date),
+            escape_html(&row.score_link),
+        ));
+    }
+
+    html.push_str("</tbody>\n</table>\n");
+    html.push_str(HTML_SCRIPT);
+
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::{
+        open_output, parse_column, render_html, select_columns, tmp_path, unique_beatmaps, CsvWriter, JsonWriter,
+        JsonlWriter, MarkdownWriter, Output, OutputFormat, Writer,
+    };
+    use serde_json::{json, Value};
+
+    fn sample_output(username: &str, pp: f32, country_rank: i32) -> Output {
+        Output {
+            username: username.to_string(),
+            user_id: 6892711,
+            profile_link: "https://osu.ppy.sh/users/6892711".to_string(),
+            country: "BY".to_string(),
+            pp: Some(pp),
+            weighted_pp: None,
+            accuracy: "97.43%".to_string(),
+            total_score: 1_000_000,
+            scorev2: false,
+            date: "2023-05-15 14:32:01".to_string(),
+            replay: true,
+            replay_available: None,
+            score_link: "https://osu.ppy.sh/scores/1".to_string(),
+            beatmap_link: "https://osu.ppy.sh/beatmapsets/1#osu/1".to_string(),
+            map: "Artist - Title".to_string(),
+            diff: "Insane".to_string(),
+            length_seconds: 180,
+            bpm: 200.0,
+            pp_per_minute: Some(100.0),
+            mods: "HDDT".to_string(),
+            max_combo: 1200,
+            count_geki: None,
+            count_katu: None,
+            is_fc: true,
+            passed: true,
+            perfect: true,
+            country_rank: Some(country_rank),
+            global_rank: Some(1000),
+            total_pp: 10000.0,
+        }
+    }
+
+    #[test]
+    fn test_columns() {
+        let columns: Vec<String> = ["username", " pp", "mods"]
+            .into_iter()
+            .map(|x| parse_column(x).unwrap())
+            .collect();
+        assert_eq!(columns, vec!["username", "pp", "mods"]);
+
+        let selected = select_columns(&sample_output("abc", 100.0, 2), &columns).unwrap();
+        assert_eq!(selected, vec![
+            ("username".to_owned(), json!("abc")),
+            ("pp".to_owned(), json!(100.0)),
+            ("mods".to_owned(), json!("HDDT")),
+        ]);
+
+        assert!(parse_column("total_score").is_ok());
+        assert!(parse_column("scorev2").is_ok());
+        assert!(parse_column("score").is_err());
+
+        let err = parse_column("bogus").unwrap_err().to_string();
+        assert!(err.contains("bogus"));
+        assert!(err.contains("username"));
+
+        assert!(parse_column("").is_err());
+    }
+
+    #[test]
+    fn test_csv_columns() {
+        let mut buf = Vec::new();
+        let columns = vec!["pp".to_owned(), "username".to_owned(), "country_rank".to_owned()];
+
+        {
+            let mut writer = Box::new(CsvWriter::new(&mut buf, Some(columns), true));
+            writer.write_row(&sample_output("abc", 100.5, 2)).unwrap();
+            writer.write_row(&Output { country_rank: None, ..sample_output("a,b", 90.0, 1) }).unwrap();
+            writer.write_row(&Output { pp: None, ..sample_output("fail", 0.0, 3) }).unwrap();
+            writer.finish().unwrap();
+        }
+
+        let expected = "\
+pp,username,country_rank
+100.5,abc,2
+90.0,\"a,b\",
+,fail,3
+";
+
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_json_columns() {
+        let columns = vec!["pp".to_owned(), "username".to_owned(), "country_rank".to_owned()];
+
+        let mut buf = Vec::new();
+        {
+            let mut writer = Box::new(JsonWriter::new(&mut buf, Vec::new(), Some(columns.clone())));
+            writer.write_row(&sample_output("abc", 100.5, 2)).unwrap();
+            writer.finish().unwrap();
+        }
+
+        let content = String::from_utf8(buf).unwrap();
+        let rows: Vec<Value> = serde_json::from_str(&content).unwrap();
+        assert_eq!(rows, vec![json!({ "pp": 100.5, "username": "abc", "country_rank": 2 })]);
+
+        // Keys follow --columns order rather than alphabetical one
+        let compact: String = content.split_whitespace().collect();
+        assert_eq!(compact, r#"[{"pp":100.5,"username":"abc","country_rank":2}]"#);
+
+        let mut buf = Vec::new();
+        {
+            let mut writer = Box::new(JsonlWriter::new(&mut buf, Some(columns)));
+            writer.write_row(&sample_output("abc", 100.5, 2)).unwrap();
+            writer.write_row(&Output { country_rank: None, ..sample_output("def", 90.0, 1) }).unwrap();
+            writer.finish().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "{\"pp\":100.5,\"username\":\"abc\",\"country_rank\":2}\n\
+             {\"pp\":90.0,\"username\":\"def\",\"country_rank\":null}\n"
+        );
+    }
+
+    #[test]
+    fn test_render_html() {
+        let mut row = sample_output("<script>alert(1)</script>", 512.5, 1);
+        row.map = "Tom & Jerry - \"Title\"".to_string();
+
+        let html = render_html(&[row]);
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<th data-numeric>pp</th>"));
+        assert!(html.contains(concat!(
+            "<tr><td>&lt;script&gt;alert(1)&lt;/script&gt;</td><td>BY</td>",
+            "<td class=\"num\" data-value=\"512.5\">512.50</td>",
+            "<td class=\"num\" data-value=\"97.43\">97.43%</td>",
+            "<td><a href=\"https://osu.ppy.sh/beatmapsets/1#osu/1\">Tom &amp; Jerry - &quot;Title&quot;</a></td>",
+            "<td>Insane</td><td>HDDT</td><td>2023-05-15 14:32:01</td>",
+            "<td><a href=\"https://osu.ppy.sh/scores/1\">link</a></td></tr>\n"
+        )));
+        assert!(!html.contains("<script>alert"));
+        assert!(html.trim_end().ends_with("</html>"));
+    }
+
+    #[test]
+    fn test_precision() {
+        let row = Output {
+            weighted_pp: Some(0.125),
+            total_pp: 12345.678,
+            ..sample_output("abc", "412.3399963".parse().unwrap(), 1)
+        };
+
+        let rounded = row.rounded(2);
+        assert_eq!(rounded.pp, Some(412.34));
+        assert_eq!(rounded.weighted_pp, Some(0.13));
+        assert_eq!(rounded.total_pp, 12345.68);
+        assert_eq!(serde_json::to_string(&rounded.pp).unwrap(), "412.34");
+
+        assert_eq!(row.rounded(0).pp, Some(412.0));
+        assert_eq!(Output { pp: None, ..row }.rounded(2).pp, None);
+
+        let mut buf = Vec::new();
+        {
+            let mut writer = CsvWriter::new(&mut buf, Some(vec!["pp".to_owned()]), true);
+            writer.write_row(&sample_output("abc", "412.3399963".parse().unwrap(), 1).rounded(1)).unwrap();
+            Box::new(writer).finish().unwrap();
+        }
+        assert_eq!(String::from_utf8(buf).unwrap(), "pp\n412.3\n");
+    }
+
+    #[test]
+    fn test_markdown_writer() {
+        let mut buf = Vec::new();
+
+        {
+            let mut writer = Box::new(MarkdownWriter::new(&mut buf));
+            writer.write_row(&sample_output("abc", 100.0, 2)).unwrap();
+            writer.write_row(&sample_output("a|b", 512.5, 12)).unwrap();
+            writer.finish().unwrap();
+        }
+
+        let expected = "\
+| username |     pp | map            | diff   | mods | date                | country_rank |
+| -------- | -----: | -------------- | ------ | ---- | ------------------- | -----------: |
+| a\\|b     | 512.50 | Artist - Title | Insane | HDDT | 2023-05-15 14:32:01 |           12 |
+| abc      | 100.00 | Artist - Title | Insane | HDDT | 2023-05-15 14:32:01 |            2 |
+";
+
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+    }
+
+    fn row(username: &str, id: i32) -> Output {
+        Output {
+            score_link: format!("https://osu.ppy.sh/scores/{id}"),
+            ..sample_output(username, 100.0, 1)
+        }
+    }
+
+    /// Writes two overlapping batches with `--append` and returns file contents
+    fn append_twice(format: OutputFormat) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "scrapper-append-{}.{}",
+            std::process::id(),
+            format.default_path()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        for rows in [vec![row("a", 1), row("b", 2)], vec![row("b", 2), row("c", 3), row("c", 3)]] {
+            let mut writer = open_output(&path, format, None, true).unwrap();
+            for r in &rows {
+                writer.write_row(r).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        content
+    }
+
+    #[test]
+    fn test_append_json() {
+        let rows: Vec<Value> = serde_json::from_str(&append_twice(OutputFormat::Json)).unwrap();
+        let usernames: Vec<&str> = rows.iter().map(|x| x["username"].as_str().unwrap()).collect();
+        assert_eq!(usernames, vec!["a", "b", "c"]);
+
+        let content = append_twice(OutputFormat::Jsonl);
+        let usernames: Vec<String> = content
+            .lines()
+            .map(|x| serde_json::from_str::<Value>(x).unwrap()["username"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(usernames, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_append_json_unfinished() {
+        let path = std::env::temp_dir().join(format!("scrapper-unfinished-{}.json", std::process::id()));
+        std::fs::write(&path, "[]").unwrap();
+
+        let mut writer = open_output(&path, OutputFormat::Json, None, true).unwrap();
+        writer.write_row(&row("a", 1)).unwrap();
+
+        // Run fails before finishing, existing file must be kept as is
+        drop(writer);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[]");
+
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(tmp_path(&path));
+    }
+
+    #[test]
+    fn test_unique_beatmaps() {
+        let path = std::env::temp_dir().join(format!("scrapper-unique-{}.jsonl", std::process::id()));
+
+        let on_map = |username: &str, pp: f32, map: u32| Output {
+            beatmap_link: format!("https://osu.ppy.sh/beatmapsets/1#osu/{map}"),
+            ..sample_output(username, pp, 1)
+        };
+
+        let mut writer = unique_beatmaps(open_output(&path, OutputFormat::Jsonl, None, false).unwrap(), true);
+        for row in [on_map("a", 100.0, 1), on_map("b", 300.0, 2), on_map("c", 200.0, 1), on_map("d", 150.0, 1)] {
+            writer.write_row(&row).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Rows keep the order maps were first seen in
+        let usernames: Vec<String> = content
+            .lines()
+            .map(|x| serde_json::from_str::<Value>(x).unwrap()["username"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(usernames, vec!["c", "b"]);
+
+        let mut writer = unique_beatmaps(Box::new(JsonlWriter::new(std::io::sink(), None)), true);
+        let without_link = Output { beatmap_link: String::new(), ..sample_output("a", 100.0, 1) };
+        assert!(writer.write_row(&without_link).is_err());
+    }
+
+    #[test]
+    fn test_append_csv_header_mismatch() {
+        let path = std::env::temp_dir().join(format!("scrapper-header-{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let columns = || Some(vec!["username".to_owned(), "pp".to_owned()]);
+
+        for _ in 0..2 {
+            let mut writer = open_output(&path, OutputFormat::Csv, columns(), true).unwrap();
+            writer.write_row(&row("a", 1)).unwrap();
+            writer.finish().unwrap();
+        }
+
+        let other = Some(vec!["pp".to_owned(), "username".to_owned()]);
+        let err = open_output(&path, OutputFormat::Csv, other, true).err().unwrap().to_string();
+        assert!(err.contains("username,pp"), "{err}");
+
+        assert!(open_output(&path, OutputFormat::Csv, None, true).is_err());
+
+        // Older output with fewer columns
+        std::fs::write(&path, "username,country,pp\na,BY,100\n").unwrap();
+        assert!(open_output(&path, OutputFormat::Csv, None, true).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_append() {
+        let content = append_twice(OutputFormat::Csv);
+
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("username,"));
+        assert!(lines[1].starts_with("a,"));
+        assert!(lines[2].starts_with("b,"));
+        assert!(lines[3].starts_with("c,"));
+    }
+}
@@ -0,0 +1,185 @@
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use eyre::Result;
+
+use std::env;
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+/// Accepted date formats along with an example of each
+const DATE_FORMATS: [(&str, &str); 3] = [
+    ("%d-%m-%Y", "01-05-2023"),
+    ("%Y-%m-%d", "2023-05-01"),
+    ("%d/%m/%Y", "01/05/2023"),
+];
+
+/// Parses date (midnight UTC) trying every accepted format in order
+pub fn parse_date(s: &str) -> Result<DateTime<Utc>> {
+    let s = s.trim();
+
+    let date = DATE_FORMATS
+        .iter()
+        .find_map(|(format, _)| NaiveDate::parse_from_str(s, format).ok());
+
+    match date {
+        Some(date) => Ok(date.and_time(NaiveTime::MIN).and_utc()),
+        None => {
+            let accepted: Vec<String> = DATE_FORMATS
+                .iter()
+                .map(|(format, example)| format!("{format} (e.g. {example})"))
+                .collect();
+
+            eyre::bail!(
+                "can't parse date {s:?}, accepted formats: {}",
+                accepted.join(", ")
+            )
+        }
+    }
+}
+
+/// Value passed on the command line, otherwise the one from
+/// environment variable `name` (if it's set)
+pub fn setting_or_env<T>(cli: Option<T>, name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    resolve_setting(cli, name, env::var(name).ok())
+}
+
+fn resolve_setting<T>(cli: Option<T>, name: &str, env_value: Option<String>) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if cli.is_some() {
+        return Ok(cli);
+    }
+
+    match env_value {
+        Some(value) => match value.trim().parse() {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(e) => eyre::bail!("invalid {name} environment variable {value:?}: {e}"),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Splits `source` into chunks of `n` chars (not bytes), nothing for empty `source`
+/// or `n == 0`. Last chunk may be shorter, with `strict` that's an error instead
+pub fn cut(source: &str, n: usize, strict: bool) -> Result<Vec<&str>> {
+    let chunks: Vec<&str> = chunks(source, n).collect();
+
+    if let Some(last) = chunks.last() {
+        if strict && last.chars().count() < n {
+            eyre::bail!("{source:?} can't be split into chunks of {n} chars, {last:?} is left over");
+        }
+    }
+
+    Ok(chunks)
+}
+
+fn chunks(mut source: &str, n: usize) -> impl Iterator<Item = &str> {
+    std::iter::from_fn(move || {
+        if source.is_empty() || n == 0 {
+            None
+        } else {
+            let end_idx = source
+                .char_indices()
+                .nth(n - 1)
+                .map_or_else(|| source.len(), |(idx, c)| idx + c.len_utf8());
+
+            let (split, rest) = source.split_at(end_idx);
+
+            source = rest;
+
+            Some(split)
+        }
+    })
+}
+
+/// Asks yes/no question on stdout, anything besides y/yes
+/// (including closed stdin) is a no
+pub fn confirm(question: &str) -> Result<bool> {
+    print!("{question} [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::{cut, parse_date, resolve_setting};
+    use chrono::{DateTime, Utc};
+
+    #[test]
+    fn test_parse_date() {
+        let expected: DateTime<Utc> = "2023-05-01T00:00:00Z".parse().unwrap();
+
+        assert_eq!(parse_date("01-05-2023").unwrap(), expected);
+        assert_eq!(parse_date("2023-05-01").unwrap(), expected);
+        assert_eq!(parse_date("01/05/2023").unwrap(), expected);
+
+        let err = parse_date("05.01.2023").unwrap_err().to_string();
+        assert!(err.contains("%d-%m-%Y (e.g. 01-05-2023)"));
+        assert!(err.contains("%Y-%m-%d (e.g. 2023-05-01)"));
+        assert!(err.contains("%d/%m/%Y (e.g. 01/05/2023)"));
+
+        assert!(parse_date("31-02-2023").is_err());
+    }
+
+    #[test]
+    fn test_resolve_setting() {
+        let env = |x: &str| Some(x.to_owned());
+
+        // Command line takes precedence even over invalid env value
+        assert_eq!(resolve_setting(Some(10u64), "OSU_TIMEOUT", env("abc")).unwrap(), Some(10));
+        assert_eq!(resolve_setting(None::<u64>, "OSU_TIMEOUT", env(" 15 ")).unwrap(), Some(15));
+        assert_eq!(resolve_setting(None::<u64>, "OSU_TIMEOUT", None).unwrap(), None);
+
+        let err = resolve_setting(None::<u64>, "OSU_TIMEOUT", env("abc")).unwrap_err().to_string();
+        assert!(err.contains("OSU_TIMEOUT"));
+        assert!(err.contains("abc"));
+    }
+
+    #[test]
+    fn test_cut() {
+        fn lenient(s: &str, n: usize) -> Vec<&str> {
+            cut(s, n, false).unwrap()
+        }
+
+        fn strict(s: &str, n: usize) -> eyre::Result<Vec<&str>> {
+            cut(s, n, true)
+        }
+
+        assert_eq!(lenient("HDDTHR", 2), vec!["HD", "DT", "HR"]);
+        assert_eq!(lenient("HDH", 2), vec!["HD", "H"]);
+        assert_eq!(lenient("H", 2), vec!["H"]);
+        assert_eq!(lenient("HDDTHR", 3), vec!["HDD", "THR"]);
+        assert_eq!(lenient("HDDTHR", 1).len(), 6);
+
+        // Chunks are split on char boundaries
+        assert_eq!(lenient("ёжик", 2), vec!["ёж", "ик"]);
+        assert_eq!(lenient("a日本", 2), vec!["a日", "本"]);
+        assert_eq!(lenient("🎵🎶x", 2), vec!["🎵🎶", "x"]);
+        // Combining mark is a char of its own
+        assert_eq!(lenient("e\u{301}e", 2), vec!["e\u{301}", "e"]);
+
+        assert!(lenient("", 2).is_empty());
+        assert!(lenient("HD", 0).is_empty());
+        assert_eq!(lenient("HD", 10), vec!["HD"]);
+
+        assert_eq!(strict("HDDTHR", 2).unwrap(), vec!["HD", "DT", "HR"]);
+        assert_eq!(strict("ёжик", 2).unwrap(), vec!["ёж", "ик"]);
+        assert!(strict("", 2).unwrap().is_empty());
+        assert!(strict("HD", 0).unwrap().is_empty());
+
+        let err = strict("HDH", 2).unwrap_err().to_string();
+        assert!(err.contains("\"H\" is left over"), "{err}");
+        assert!(strict("a日本", 2).is_err());
+        assert!(strict("HD", 10).is_err());
+    }
+}